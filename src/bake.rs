@@ -0,0 +1,505 @@
+//! `rocker bake`: builds several images from one JSON or TOML definition.
+//!
+//! The file follows the buildx bake layout of `variable`, `group` and `target`
+//! blocks, reduced to what the classic `/build` endpoint supports.
+
+use crate::{
+    context,
+    image::{
+        self,
+        BuildArgOptions
+    },
+    term,
+};
+use futures::future::join_all;
+use serde::{
+    Deserialize,
+    Serialize
+};
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    env,
+    fs,
+    path::Path,
+    process,
+    time::Instant,
+};
+
+/// Files looked up in the working directory when `--file` is not given
+const DEFAULT_FILES: [&str; 2] = ["docker-bake.json", "docker-bake.toml"];
+
+ # [derive(Deserialize, Debug, Default)]
+struct BakeFile {
+     # [serde(default)]
+    variable: BTreeMap<String, Variable>,
+     # [serde(default)]
+    group: BTreeMap<String, Group>,
+     # [serde(default)]
+    target: BTreeMap<String, Target>,
+}
+
+ # [derive(Deserialize, Debug)]
+struct Variable {
+    default: Option<Value>,
+}
+
+ # [derive(Deserialize, Serialize, Debug)]
+struct Group {
+    targets: Vec<String>,
+}
+
+ # [derive(Deserialize, Serialize, Debug, Clone, Default)]
+ # [serde(rename_all = "kebab-case")]
+struct Target {
+     # [serde(default, skip_serializing)]
+    inherits: Vec<String>,
+     # [serde(skip_serializing_if = "Option::is_none")]
+    context: Option<String>,
+     # [serde(skip_serializing_if = "Option::is_none")]
+    dockerfile: Option<String>,
+     # [serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    args: BTreeMap<String, String>,
+     # [serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    labels: BTreeMap<String, String>,
+     # [serde(skip_serializing_if = "Option::is_none")]
+    tags: Option<Vec<String>>,
+     # [serde(skip_serializing_if = "Option::is_none")]
+    target: Option<String>,
+     # [serde(skip_serializing_if = "Option::is_none")]
+    cache_from: Option<Vec<String>>,
+     # [serde(skip_serializing_if = "Option::is_none")]
+    network: Option<String>,
+     # [serde(skip_serializing_if = "Option::is_none")]
+    no_cache: Option<bool>,
+     # [serde(skip_serializing_if = "Option::is_none")]
+    pull: Option<bool>,
+     # [serde(skip_serializing_if = "Option::is_none")]
+    output: Option<Vec<String>>,
+}
+
+impl Target {
+    /// Layers `self` over `parent`: values set here win, args and labels are merged
+    fn over(self, parent: &Target) -> Target {
+        let mut args = parent.args.clone();
+        args.extend(self.args);
+        let mut labels = parent.labels.clone();
+        labels.extend(self.labels);
+        Target {
+            inherits: Vec::new(),
+            context: self.context.or_else(|| parent.context.clone()),
+            dockerfile: self.dockerfile.or_else(|| parent.dockerfile.clone()),
+            args,
+            labels,
+            tags: self.tags.or_else(|| parent.tags.clone()),
+            target: self.target.or_else(|| parent.target.clone()),
+            cache_from: self.cache_from.or_else(|| parent.cache_from.clone()),
+            network: self.network.or_else(|| parent.network.clone()),
+            no_cache: self.no_cache.or(parent.no_cache),
+            pull: self.pull.or(parent.pull),
+            output: self.output.or_else(|| parent.output.clone()),
+        }
+    }
+
+    /// Substitutes `${VAR}` in every string of the target
+    fn expand(self, variables: &BTreeMap<String, String>) -> Result<Target, String> {
+        let one = |value: Option<String>| value.map(|v| expand(&v, variables)).transpose();
+        let list = |values: Option<Vec<String>>| {
+            values.map(|values| values.iter().map(|v| expand(v, variables)).collect()).transpose()
+        };
+        let map = |values: BTreeMap<String, String>| -> Result<BTreeMap<String, String>, String> {
+            values.into_iter().map(|(k, v)| Ok((k, expand(&v, variables)?))).collect()
+        };
+        Ok(Target {
+            inherits: Vec::new(),
+            context: one(self.context)?,
+            dockerfile: one(self.dockerfile)?,
+            args: map(self.args)?,
+            labels: map(self.labels)?,
+            tags: list(self.tags)?,
+            target: one(self.target)?,
+            cache_from: list(self.cache_from)?,
+            network: one(self.network)?,
+            no_cache: self.no_cache,
+            pull: self.pull,
+            output: list(self.output)?,
+        })
+    }
+
+    /// The `rocker build` flags this target stands for, and its context
+    fn build_options(&self) -> Result<(String, BuildArgOptions), String> {
+        let context = self.context.clone().unwrap_or_else(|| ".".to_string());
+        let mut options = BuildArgOptions::default();
+        // Like buildx, the Dockerfile is relative to the context
+        options.file = self.dockerfile.as_ref().map(|dockerfile| {
+            if context::is_remote(&context) || Path::new(dockerfile).is_absolute() {
+                dockerfile.clone()
+            }
+            else {
+                Path::new(&context).join(dockerfile).to_string_lossy().to_string()
+            }
+        });
+        options.build_arg = Some(self.args.iter().map(|(k, v)| format!("{}={}", k, v)).collect());
+        options.label = Some(self.labels.iter().map(|(k, v)| format!("{}={}", k, v)).collect());
+        options.tag = self.tags.clone();
+        options.target = self.target.clone();
+        options.cache_from = self.cache_from.clone();
+        options.network = self.network.clone();
+        options.no_cache = self.no_cache.unwrap_or(false);
+        options.pull = self.pull.unwrap_or(false);
+        options.output = match self.output.as_deref() {
+            None | Some([]) => None,
+            Some([output]) => Some(output.clone()),
+            Some(_) => return Err("only one output per target is supported".to_string()),
+        };
+        Ok((context, options))
+    }
+}
+
+/// Replaces `${NAME}` with the value of a declared variable; `$$` is a literal `$`
+fn expand(text: &str, variables: &BTreeMap<String, String>) -> Result<String, String> {
+    let mut expanded = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(after) = rest.strip_prefix("$$") {
+            expanded.push('$');
+            rest = after;
+        }
+        else if let Some(after) = rest.strip_prefix("${") {
+            let end = after.find('}').ok_or_else(|| format!("unterminated variable in {:?}", text))?;
+            let name = &after[..end];
+            let value = variables.get(name).ok_or_else(|| format!("variable {:?} is not defined", name))?;
+            expanded.push_str(value);
+            rest = &after[end + 1..];
+        }
+        else {
+            expanded.push('$');
+            rest = &rest[1..];
+        }
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+fn load(file: Option<&str>) -> Result<BakeFile, String> {
+    let path = match file {
+        Some(file) => file.to_string(),
+        None => DEFAULT_FILES
+            .iter()
+            .find(|name| Path::new(name).exists())
+            .map(|name| name.to_string())
+            .ok_or_else(|| format!("couldn't find a bake definition, looked for {}", DEFAULT_FILES.join(", ")))?,
+    };
+    let contents = fs::read_to_string(&path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+    if path.ends_with(".toml") {
+        toml::from_str(&contents).map_err(|e| format!("failed to parse {}: {}", path, e))
+    }
+    else {
+        serde_json::from_str(&contents).map_err(|e| format!("failed to parse {}: {}", path, e))
+    }
+}
+
+impl BakeFile {
+    /// Variable values: the environment overrides the declared default
+    fn variables(&self) -> BTreeMap<String, String> {
+        self.variable
+            .iter()
+            .map(|(name, variable)| {
+                let value = env::var(name).unwrap_or_else(|_| match &variable.default {
+                    Some(Value::String(default)) => default.clone(),
+                    Some(Value::Null) | None => String::new(),
+                    Some(default) => default.to_string(),
+                });
+                (name.clone(), value)
+            })
+            .collect()
+    }
+
+    /// What to build when no target is named: "default", or else every target
+    fn default_targets(&self) -> Vec<String> {
+        if self.group.contains_key("default") || self.target.contains_key("default") {
+            vec!["default".to_string()]
+        }
+        else {
+            self.target.keys().cloned().collect()
+        }
+    }
+
+    /// Expands groups into target names, keeping the first occurrence of each
+    fn target_names(&self, names: &[String], seen: &mut Vec<String>, out: &mut Vec<String>) -> Result<(), String> {
+        for name in names {
+            if let Some(group) = self.group.get(name) {
+                if seen.contains(name) {
+                    return Err(format!("group {} includes itself", name));
+                }
+                seen.push(name.clone());
+                self.target_names(&group.targets, seen, out)?;
+                seen.pop();
+            }
+            else if self.target.contains_key(name) {
+                if !out.contains(name) {
+                    out.push(name.clone());
+                }
+            }
+            else {
+                return Err(format!("failed to find target {}", name));
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies `inherits` recursively
+    fn resolve(&self, name: &str, chain: &mut Vec<String>) -> Result<Target, String> {
+        if chain.iter().any(|seen| seen == name) {
+            return Err(format!("target {} inherits from itself", name));
+        }
+        let target = self.target.get(name).ok_or_else(|| format!("failed to find target {}", name))?;
+        chain.push(name.to_string());
+        let mut base = Target::default();
+        for parent in &target.inherits {
+            base = self.resolve(parent, chain)?.over(&base);
+        }
+        chain.pop();
+        Ok(target.clone().over(&base))
+    }
+}
+
+struct Outcome {
+    name: String,
+    tags: Vec<String>,
+    result: Result<Option<String>, image::BuildFailure>,
+    seconds: f64,
+}
+
+pub async fn bake(targets: Vec<String>, file: Option<String>, print: bool, no_cache: bool, pull: bool) {
+    let bake_file = match load(file.as_deref()) {
+        Ok(bake_file) => bake_file,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    };
+
+    let requested = if targets.is_empty() { bake_file.default_targets() } else { targets };
+    let mut names = Vec::new();
+    let variables = bake_file.variables();
+    let resolved: Result<Vec<(String, Target)>, String> = bake_file
+        .target_names(&requested, &mut Vec::new(), &mut names)
+        .and_then(|_| {
+            names
+                .iter()
+                .map(|name| {
+                    let mut target = bake_file.resolve(name, &mut Vec::new())?.expand(&variables)?;
+                    if no_cache {
+                        target.no_cache = Some(true);
+                    }
+                    if pull {
+                        target.pull = Some(true);
+                    }
+                    Ok((name.clone(), target))
+                })
+                .collect()
+        });
+    let resolved = match resolved {
+        Ok(resolved) => resolved,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    };
+
+    if print {
+        let definition = serde_json::json!({
+            "group": {"default": Group { targets: names }},
+            "target": resolved.iter().cloned().collect::<BTreeMap<_, _>>(),
+        });
+        println!("{}", serde_json::to_string_pretty(&definition).unwrap());
+        return;
+    }
+
+    let builds = resolved.iter().map(|(name, target)| async move {
+        let started = Instant::now();
+        let result = match target.build_options() {
+            Ok((context, options)) => image::build_image(&context, &options, Some(name)).await,
+            Err(e) => Err(e.into()),
+        };
+        if let Err(failure) = &result {
+            eprintln!("[{}] Error: {}", name, failure.message);
+        }
+        Outcome {
+            name: name.clone(),
+            tags: target.tags.clone().unwrap_or_default(),
+            result,
+            seconds: started.elapsed().as_secs_f64(),
+        }
+    });
+    let outcomes = join_all(builds).await;
+
+    println!();
+    let rows: Vec<Vec<String>> = outcomes
+        .iter()
+        .map(|outcome| {
+            let (status, image) = match &outcome.result {
+                Ok(id) => ("done", id.as_deref().map(term::short_id).unwrap_or_default()),
+                Err(_) => ("failed", String::new()),
+            };
+            vec![
+                outcome.name.clone(),
+                status.to_string(),
+                image,
+                outcome.tags.join(", "),
+                format!("{:.1}s", outcome.seconds),
+            ]
+        })
+        .collect();
+    term::print_table(&["TARGET", "STATUS", "IMAGE", "TAGS", "DURATION"], &rows);
+    if outcomes.iter().any(|outcome| outcome.result.is_err()) {
+        process::exit(1);
+    }
+}
+
+ # [cfg(test)]
+mod tests {
+    use super::{
+        BakeFile,
+        Target
+    };
+    use std::{
+        collections::BTreeMap,
+        env
+    };
+
+    fn bake_file(json: &str) -> BakeFile {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn names(bake_file: &BakeFile, requested: &[&str]) -> Result<Vec<String>, String> {
+        let requested: Vec<String> = requested.iter().map(|name| name.to_string()).collect();
+        let mut out = Vec::new();
+        bake_file.target_names(&requested, &mut Vec::new(), &mut out).map(|_| out)
+    }
+
+     # [test]
+    fn values_set_on_a_target_win_over_its_parent() {
+        let parent = Target {
+            context: Some("parent".to_string()),
+            dockerfile: Some("Dockerfile.parent".to_string()),
+            args: [("A", "parent"), ("B", "parent")].iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            tags: Some(vec!["parent:1".to_string()]),
+            no_cache: Some(true),
+            ..Target::default()
+        };
+        let child = Target {
+            inherits: vec!["parent".to_string()],
+            context: Some("child".to_string()),
+            args: [("B", "child"), ("C", "child")].iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            tags: Some(Vec::new()),
+            no_cache: Some(false),
+            ..Target::default()
+        };
+        let merged = child.over(&parent);
+        assert!(merged.inherits.is_empty());
+        assert_eq!(merged.context.as_deref(), Some("child"));
+        assert_eq!(merged.dockerfile.as_deref(), Some("Dockerfile.parent"));
+        assert_eq!(merged.args["A"], "parent");
+        assert_eq!(merged.args["B"], "child");
+        assert_eq!(merged.args["C"], "child");
+        // An explicitly empty list still overrides
+        assert_eq!(merged.tags, Some(Vec::new()));
+        assert_eq!(merged.no_cache, Some(false));
+    }
+
+     # [test]
+    fn inherits_applies_parents_in_order() {
+        let file = bake_file(
+            r#"{"target": {
+                "base": {"context": "base", "args": {"A": "base", "B": "base"}, "tags": ["base"]},
+                "second": {"inherits": ["base"], "args": {"B": "second"}, "dockerfile": "Dockerfile.second"},
+                "third": {"args": {"B": "third", "C": "third"}, "dockerfile": "Dockerfile.third"},
+                "app": {"inherits": ["second", "third"], "args": {"C": "app"}, "tags": ["app"]},
+                "loop": {"inherits": ["loop2"]},
+                "loop2": {"inherits": ["loop"]},
+                "orphan": {"inherits": ["missing"]}
+            }}"#,
+        );
+        let app = file.resolve("app", &mut Vec::new()).unwrap();
+        assert_eq!(app.context.as_deref(), Some("base"));
+        // Later parents override earlier ones, the target itself overrides both
+        assert_eq!(app.dockerfile.as_deref(), Some("Dockerfile.third"));
+        assert_eq!(app.args["A"], "base");
+        assert_eq!(app.args["B"], "third");
+        assert_eq!(app.args["C"], "app");
+        assert_eq!(app.tags, Some(vec!["app".to_string()]));
+
+        assert!(file.resolve("loop", &mut Vec::new()).unwrap_err().contains("inherits from itself"));
+        assert!(file.resolve("orphan", &mut Vec::new()).unwrap_err().contains("failed to find target missing"));
+    }
+
+     # [test]
+    fn variables_expand_with_environment_overrides() {
+        env::set_var("ROCKER_BAKE_TEST_TAG", "from-env");
+        env::remove_var("ROCKER_BAKE_TEST_REGISTRY");
+        let file = bake_file(
+            r#"{
+                "variable": {
+                    "ROCKER_BAKE_TEST_TAG": {"default": "from-file"},
+                    "ROCKER_BAKE_TEST_REGISTRY": {"default": "registry.local"},
+                    "ROCKER_BAKE_TEST_NUMBER": {"default": 3},
+                    "ROCKER_BAKE_TEST_EMPTY": {}
+                },
+                "target": {"app": {"tags": ["${ROCKER_BAKE_TEST_REGISTRY}/app:${ROCKER_BAKE_TEST_TAG}"]}}
+            }"#,
+        );
+        let variables = file.variables();
+        assert_eq!(variables["ROCKER_BAKE_TEST_NUMBER"], "3");
+        assert_eq!(variables["ROCKER_BAKE_TEST_EMPTY"], "");
+
+        let app = file.resolve("app", &mut Vec::new()).unwrap().expand(&variables).unwrap();
+        assert_eq!(app.tags, Some(vec!["registry.local/app:from-env".to_string()]));
+
+        let target = |args: &[(&str, &str)]| Target {
+            args: args.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            ..Target::default()
+        };
+        let expanded = target(&[("PRICE", "$$5 and $HOME"), ("N", "${ROCKER_BAKE_TEST_NUMBER}")]).expand(&variables).unwrap();
+        assert_eq!(expanded.args["PRICE"], "$5 and $HOME");
+        assert_eq!(expanded.args["N"], "3");
+        assert!(target(&[("X", "${UNDECLARED}")]).expand(&variables).unwrap_err().contains("not defined"));
+        assert!(target(&[("X", "${OPEN")]).expand(&BTreeMap::new()).unwrap_err().contains("unterminated"));
+    }
+
+     # [test]
+    fn groups_expand_to_unique_targets() {
+        let file = bake_file(
+            r#"{
+                "group": {
+                    "default": {"targets": ["all"]},
+                    "all": {"targets": ["web", "backend", "web"]},
+                    "backend": {"targets": ["api", "worker", "web"]},
+                    "self": {"targets": ["self"]},
+                    "ping": {"targets": ["pong"]},
+                    "pong": {"targets": ["ping"]},
+                    "broken": {"targets": ["web", "nope"]}
+                },
+                "target": {"web": {}, "api": {}, "worker": {}}
+            }"#,
+        );
+        assert_eq!(names(&file, &["default"]).unwrap(), ["web", "api", "worker"]);
+        assert_eq!(names(&file, &["worker", "backend"]).unwrap(), ["worker", "api", "web"]);
+        assert!(names(&file, &["self"]).unwrap_err().contains("group self includes itself"));
+        assert!(names(&file, &["ping"]).unwrap_err().contains("includes itself"));
+        assert!(names(&file, &["broken"]).unwrap_err().contains("failed to find target nope"));
+    }
+
+     # [test]
+    fn default_targets() {
+        let group = bake_file(r#"{"group": {"default": {"targets": ["b"]}}, "target": {"a": {}, "b": {}}}"#);
+        assert_eq!(group.default_targets(), ["default"]);
+        let target = bake_file(r#"{"target": {"a": {}, "default": {}}}"#);
+        assert_eq!(target.default_targets(), ["default"]);
+        let neither = bake_file(r#"{"target": {"b": {}, "a": {}}}"#);
+        assert_eq!(neither.default_targets(), ["a", "b"]);
+    }
+}
@@ -0,0 +1,211 @@
+//! `rocker builder`: inspecting and reclaiming the daemon's build cache.
+
+use crate::engine::{
+    self,
+    Engine
+};
+use crate::{
+    term,
+    units
+};
+use chrono::DateTime;
+use clap::Subcommand;
+use serde::Deserialize;
+use std::{
+    cmp::Reverse,
+    process
+};
+
+ # [derive(Subcommand, Debug)]
+pub enum BuilderCommand {
+    /// Show build cache disk usage
+    Du {
+        /// Provide a more verbose output
+         # [arg(short, long)]
+        verbose: bool,
+    },
+
+    /// Remove build cache
+    Prune {
+        /// Remove all unused build cache, not just dangling ones
+         # [arg(short, long)]
+        all: bool,
+
+        /// Provide filter values (e.g. "until=24h")
+         # [arg(long, value_name = "filter")]
+        filter: Option < Vec < String >> ,
+
+        /// Do not prompt for confirmation
+         # [arg(short, long)]
+        force: bool,
+
+        /// Amount of disk space to keep for cache
+         # [arg(long, value_name = "bytes")]
+        keep_storage: Option < String > ,
+    },
+}
+
+pub async fn handle_builder_command(command: &BuilderCommand) {
+    match command {
+        BuilderCommand::Du {
+            verbose
+        }
+         => {
+            du(*verbose).await;
+        }
+        BuilderCommand::Prune {
+            all,
+            filter,
+            force,
+            keep_storage,
+        }
+         => {
+            prune(*all, filter.clone().unwrap_or_default(), *force, keep_storage.as_deref()).await;
+        }
+    }
+}
+
+ # [derive(Deserialize, Debug)]
+ # [serde(rename_all = "PascalCase")]
+struct DiskUsage {
+    build_cache: Option<Vec<CacheRecord>>,
+}
+
+ # [derive(Deserialize, Debug)]
+ # [serde(rename_all = "PascalCase")]
+struct CacheRecord {
+     # [serde(rename = "ID")]
+    id: String,
+    parents: Option<Vec<String>>,
+     # [serde(rename = "Type")]
+    kind: String,
+     # [serde(default)]
+    description: String,
+    in_use: bool,
+    shared: bool,
+    size: i64,
+    created_at: String,
+    last_used_at: Option<String>,
+     # [serde(default)]
+    usage_count: i64,
+}
+
+fn ago(timestamp: Option<&str>) -> String {
+    timestamp
+        .and_then(|timestamp| DateTime::parse_from_rfc3339(timestamp).ok())
+        .map(|time| units::time_ago(time.timestamp()))
+        .unwrap_or_default()
+}
+
+pub async fn du(verbose: bool) {
+    let engine = Engine::new();
+    let usage: DiskUsage = match engine.get_json("/system/df?type=build-cache").await {
+        Ok(usage) => usage,
+        Err(e) => {
+            eprintln!("Error: {}", engine::error_message(&e));
+            process::exit(1);
+        }
+    };
+    let mut records = usage.build_cache.unwrap_or_default();
+    records.sort_by_key(|record| Reverse(record.size));
+
+    if verbose {
+        for record in &records {
+            println!("ID:\t\t{}", record.id);
+            if let Some(parents) = record.parents.as_ref().filter(|parents| !parents.is_empty()) {
+                println!("Parent:\t\t{}", parents.join(", "));
+            }
+            println!("Type:\t\t{}", record.kind);
+            println!("Description:\t{}", record.description);
+            println!("Created at:\t{}", record.created_at);
+            println!("In use:\t\t{}", record.in_use);
+            println!("Shared:\t\t{}", record.shared);
+            println!("Reclaimable:\t{}", !record.in_use);
+            println!("Size:\t\t{}", units::human_size(record.size as f64));
+            println!("Last used:\t{}", ago(record.last_used_at.as_deref()));
+            println!("Usage count:\t{}", record.usage_count);
+            println!();
+        }
+    }
+    else {
+        let rows: Vec<Vec<String>> = records
+            .iter()
+            .map(|record| {
+                vec![
+                    record.id.clone(),
+                    record.kind.clone(),
+                    (!record.in_use).to_string(),
+                    units::human_size(record.size as f64),
+                    ago(record.last_used_at.as_deref()),
+                ]
+            })
+            .collect();
+        term::print_table(&["ID", "TYPE", "RECLAIMABLE", "SIZE", "LAST ACCESSED"], &rows);
+    }
+
+    let total = |keep: &dyn Fn(&CacheRecord) -> bool| -> String {
+        units::human_size(records.iter().filter(|record| keep(record)).map(|record| record.size).sum::<i64>() as f64)
+    };
+    if !verbose && !records.is_empty() {
+        println!();
+    }
+    println!("Shared:\t\t{}", total(&|record| record.shared));
+    println!("Private:\t{}", total(&|record| !record.shared));
+    println!("Reclaimable:\t{}", total(&|record| !record.in_use));
+    println!("Total:\t\t{}", total(&|_| true));
+}
+
+ # [derive(Deserialize, Debug)]
+ # [serde(rename_all = "PascalCase")]
+struct PruneReport {
+    caches_deleted: Option<Vec<String>>,
+    space_reclaimed: u64,
+}
+
+pub async fn prune(all: bool, filter: Vec<String>, force: bool, keep_storage: Option<&str>) {
+    let mut params = vec![("all", all.to_string())];
+    match engine::filters(&filter) {
+        Ok(filters) => params.push(("filters", filters)),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    }
+    if let Some(keep_storage) = keep_storage {
+        match units::parse_bytes(keep_storage) {
+            Ok(bytes) => params.push(("keep-storage", bytes.to_string())),
+            Err(e) => {
+                eprintln!("Error: invalid argument {:?} for \"--keep-storage\" flag: {}", keep_storage, e);
+                process::exit(1);
+            }
+        }
+    }
+
+    let warning = if all {
+        "WARNING! This will remove all build cache."
+    }
+    else {
+        "WARNING! This will remove all dangling build cache."
+    };
+    if !force && !term::confirm(warning) {
+        return;
+    }
+
+    let engine = Engine::new();
+    let report: PruneReport = match engine.post_json(&engine::query("/build/prune", params), None).await {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("Error: {}", engine::error_message(&e));
+            process::exit(1);
+        }
+    };
+    let deleted = report.caches_deleted.unwrap_or_default();
+    if !deleted.is_empty() {
+        println!("Deleted build cache objects:");
+        for id in deleted {
+            println!("{}", id);
+        }
+        println!();
+    }
+    println!("Total reclaimed space: {}", units::human_size(report.space_reclaimed as f64));
+}
@@ -0,0 +1,515 @@
+//! BuildKit support for `rocker build`.
+//!
+//! Secrets and SSH agents reach a BuildKit build through a client session: the
+//! daemon upgrades `POST /session` to HTTP/2 and then calls back into rocker with
+//! gRPC, so rocker plays the gRPC server on that connection. The messages are
+//! small enough that their protobuf encoding is written out by hand here.
+//! The module also turns the BuildKit progress trace into plain text.
+
+use crate::engine::{
+    self,
+    Engine
+};
+use base64::{
+    engine::general_purpose::STANDARD,
+    Engine as _
+};
+use h2::{
+    server::SendResponse,
+    RecvStream,
+    SendStream
+};
+use hyper::body::Bytes;
+use std::{
+    collections::HashMap,
+    env,
+    fs,
+    os::unix::fs::FileTypeExt,
+    path::{
+        Path,
+        PathBuf
+    },
+    process,
+    sync::Arc,
+    time::{
+        SystemTime,
+        UNIX_EPOCH
+    },
+};
+use tokio::{
+    io::{
+        AsyncReadExt,
+        AsyncWriteExt
+    },
+    net::UnixStream,
+    task::JoinHandle,
+};
+
+const HEALTH_CHECK: &str = "/grpc.health.v1.Health/Check";
+const GET_SECRET: &str = "/moby.buildkit.secrets.v1.Secrets/GetSecret";
+const CHECK_AGENT: &str = "/moby.sshforward.v1.SSH/CheckAgent";
+const FORWARD_AGENT: &str = "/moby.sshforward.v1.SSH/ForwardAgent";
+
+/// gRPC status codes used by the session services
+const GRPC_NOT_FOUND: u32 = 5;
+const GRPC_UNIMPLEMENTED: u32 = 12;
+const GRPC_UNAVAILABLE: u32 = 14;
+
+/// Reads a `--secret id=ID[,src=PATH|,env=VAR]` value.
+///
+/// Without `src` or `env`, the environment variable named like the secret is used.
+pub fn parse_secret(spec: &str) -> Result<(String, Vec<u8>), String> {
+    let mut id = None;
+    let mut src = None;
+    let mut env_name = None;
+    let mut from_env = false;
+    for field in spec.split(',') {
+        match field.split_once('=') {
+            Some(("id", value)) => id = Some(value.to_string()),
+            Some(("src" | "source", value)) => src = Some(value.to_string()),
+            Some(("env", value)) => env_name = Some(value.to_string()),
+            Some(("type", "file")) => from_env = false,
+            Some(("type", "env")) => from_env = true,
+            _ => return Err(format!("invalid secret {:?}: unexpected field {:?}", spec, field)),
+        }
+    }
+    let id = id.filter(|id| !id.is_empty()).ok_or_else(|| format!("invalid secret {:?}: id is required", spec))?;
+    // With type=env, src names the variable rather than a file
+    if from_env {
+        env_name = env_name.or(src.take());
+    }
+    if let Some(src) = src {
+        let path = match src.strip_prefix("~/") {
+            Some(rest) => PathBuf::from(env::var("HOME").unwrap_or_default()).join(rest),
+            None => PathBuf::from(&src),
+        };
+        let data = fs::read(&path).map_err(|e| format!("failed to read secret {}: {}: {}", id, path.display(), e))?;
+        return Ok((id, data));
+    }
+    let name = env_name.unwrap_or_else(|| id.clone());
+    match env::var_os(&name) {
+        Some(value) => Ok((id, value.to_string_lossy().into_owned().into_bytes())),
+        None => Err(format!("secret {}: environment variable {} is not set", id, name)),
+    }
+}
+
+/// Reads a `--ssh default|ID[=SOCKET]` value
+pub fn parse_ssh(spec: &str) -> Result<(String, PathBuf), String> {
+    let (id, socket) = match spec.split_once('=') {
+        Some((id, socket)) => (id.to_string(), Some(socket.to_string())),
+        None => (spec.to_string(), None),
+    };
+    if id.is_empty() {
+        return Err(format!("invalid ssh {:?}: id is required", spec));
+    }
+    let socket = match socket.or_else(|| env::var("SSH_AUTH_SOCK").ok()) {
+        Some(socket) => PathBuf::from(socket),
+        None => return Err(format!("invalid ssh {:?}: no socket given and SSH_AUTH_SOCK is not set", spec)),
+    };
+    let metadata = fs::metadata(&socket).map_err(|e| format!("ssh {}: {}: {}", id, socket.display(), e))?;
+    if !metadata.file_type().is_socket() {
+        return Err(format!("ssh {}: {} is not an agent socket; key files are not supported", id, socket.display()));
+    }
+    Ok((id, socket))
+}
+
+/// What rocker serves to the daemon during a build
+pub struct Session {
+    id: String,
+    secrets: HashMap<String, Vec<u8>>,
+    agents: HashMap<String, PathBuf>,
+}
+
+/// Stops serving the session when dropped
+pub struct SessionHandle {
+    task: JoinHandle<()>,
+}
+
+impl Drop for SessionHandle {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl Session {
+    pub fn new(secrets: Vec<(String, Vec<u8>)>, agents: Vec<(String, PathBuf)>) -> Session {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+        Session {
+            id: format!("{:x}{:x}", process::id(), nanos),
+            secrets: secrets.into_iter().collect(),
+            agents: agents.into_iter().collect(),
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Attaches the session to the daemon and serves it in the background
+    pub async fn start(self, engine: &Engine) -> Result<SessionHandle, String> {
+        let mut headers = vec![
+            ("X-Docker-Expose-Session-Uuid", self.id.clone()),
+            ("X-Docker-Expose-Session-Name", "rocker".to_string()),
+            ("X-Docker-Expose-Session-Sharedkey", self.id.clone()),
+            ("X-Docker-Expose-Session-Grpc-Method", HEALTH_CHECK.to_string()),
+        ];
+        if !self.secrets.is_empty() {
+            headers.push(("X-Docker-Expose-Session-Grpc-Method", GET_SECRET.to_string()));
+        }
+        if !self.agents.is_empty() {
+            headers.push(("X-Docker-Expose-Session-Grpc-Method", CHECK_AGENT.to_string()));
+            headers.push(("X-Docker-Expose-Session-Grpc-Method", FORWARD_AGENT.to_string()));
+        }
+        let connection = engine
+            .upgrade("/session", "h2c", headers)
+            .await
+            .map_err(|e| format!("failed to start a build session: {}", engine::error_message(&e)))?;
+
+        let session = Arc::new(self);
+        let task = tokio::spawn(async move {
+            let mut server = match h2::server::handshake(connection).await {
+                Ok(server) => server,
+                Err(_) => return,
+            };
+            while let Some(Ok((request, respond))) = server.accept().await {
+                let session = session.clone();
+                tokio::spawn(async move {
+                    let _ = session.call(request, respond).await;
+                });
+            }
+        });
+        Ok(SessionHandle { task })
+    }
+
+    async fn call(&self, request: http::Request<RecvStream>, mut respond: SendResponse<Bytes>) -> Result<(), h2::Error> {
+        let method = request.uri().path().to_string();
+        let ssh_id = request
+            .headers()
+            .get("buildkit.ssh.id")
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("default")
+            .to_string();
+        let mut body = request.into_body();
+        let response = http::Response::builder()
+            .status(200)
+            .header("content-type", "application/grpc")
+            .body(())
+            .unwrap();
+        let mut stream = respond.send_response(response, false)?;
+
+        let status = match method.as_str() {
+            HEALTH_CHECK => {
+                read_message(&mut body).await?;
+                // HealthCheckResponse { status: SERVING }
+                stream.send_data(grpc_frame(&[0x08, 0x01]), false)?;
+                Ok(())
+            }
+            GET_SECRET => {
+                let request = read_message(&mut body).await?.unwrap_or_default();
+                let id = string_field(&request, 1).unwrap_or_default();
+                match self.secrets.get(&id) {
+                    Some(data) => {
+                        stream.send_data(grpc_frame(&bytes_field(1, data)), false)?;
+                        Ok(())
+                    }
+                    None => Err((GRPC_NOT_FOUND, format!("secret {} not found", id))),
+                }
+            }
+            CHECK_AGENT => {
+                let request = read_message(&mut body).await?.unwrap_or_default();
+                let id = string_field(&request, 1).filter(|id| !id.is_empty()).unwrap_or_else(|| "default".to_string());
+                if self.agents.contains_key(&id) {
+                    stream.send_data(grpc_frame(&[]), false)?;
+                    Ok(())
+                }
+                else {
+                    Err((GRPC_NOT_FOUND, format!("unset ssh forward key {}", id)))
+                }
+            }
+            FORWARD_AGENT => match self.agents.get(&ssh_id) {
+                Some(socket) => forward_agent(socket, &mut body, &mut stream).await,
+                None => Err((GRPC_NOT_FOUND, format!("unset ssh forward key {}", ssh_id))),
+            },
+            _ => Err((GRPC_UNIMPLEMENTED, format!("unknown method {}", method))),
+        };
+
+        let mut trailers = http::HeaderMap::new();
+        match status {
+            Ok(()) => {
+                trailers.insert("grpc-status", http::HeaderValue::from(0));
+            }
+            Err((code, message)) => {
+                trailers.insert("grpc-status", http::HeaderValue::from(code));
+                if let Ok(message) = http::HeaderValue::from_str(&message) {
+                    trailers.insert("grpc-message", message);
+                }
+            }
+        }
+        stream.send_trailers(trailers)
+    }
+}
+
+/// Relays `BytesMessage` frames between the daemon and a local SSH agent
+async fn forward_agent(socket: &Path, body: &mut RecvStream, stream: &mut SendStream<Bytes>) -> Result<(), (u32, String)> {
+    let agent = UnixStream::connect(socket)
+        .await
+        .map_err(|e| (GRPC_UNAVAILABLE, format!("failed to connect to ssh agent: {}", e)))?;
+    let (mut reader, mut writer) = agent.into_split();
+    let mut frames = Frames::default();
+    let mut buffer = vec![0u8; 16 * 1024];
+    loop {
+        tokio::select! {
+            chunk = body.data() => {
+                let chunk = match chunk {
+                    Some(Ok(chunk)) => chunk,
+                    _ => return Ok(()),
+                };
+                let _ = body.flow_control().release_capacity(chunk.len());
+                frames.push(&chunk);
+                while let Some(message) = frames.next_message() {
+                    let data = bytes_of(&message, 1).unwrap_or_default();
+                    if writer.write_all(data).await.is_err() {
+                        return Ok(());
+                    }
+                }
+            }
+            read = reader.read(&mut buffer) => {
+                match read {
+                    Ok(0) | Err(_) => return Ok(()),
+                    Ok(n) => {
+                        if stream.send_data(grpc_frame(&bytes_field(1, &buffer[..n])), false).is_err() {
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Reads the first gRPC message of a request
+async fn read_message(body: &mut RecvStream) -> Result<Option<Vec<u8>>, h2::Error> {
+    let mut frames = Frames::default();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        let _ = body.flow_control().release_capacity(chunk.len());
+        frames.push(&chunk);
+        if let Some(message) = frames.next_message() {
+            return Ok(Some(message));
+        }
+    }
+    Ok(None)
+}
+
+/// Splits a byte stream into length-prefixed gRPC messages
+ # [derive(Default)]
+struct Frames {
+    buffer: Vec<u8>,
+}
+
+impl Frames {
+    fn push(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    fn next_message(&mut self) -> Option<Vec<u8>> {
+        if self.buffer.len() < 5 {
+            return None;
+        }
+        let length = u32::from_be_bytes([self.buffer[1], self.buffer[2], self.buffer[3], self.buffer[4]]) as usize;
+        if self.buffer.len() < 5 + length {
+            return None;
+        }
+        let message = self.buffer[5..5 + length].to_vec();
+        self.buffer.drain(..5 + length);
+        Some(message)
+    }
+}
+
+fn grpc_frame(message: &[u8]) -> Bytes {
+    let mut frame = Vec::with_capacity(5 + message.len());
+    frame.push(0);
+    frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    frame.extend_from_slice(message);
+    Bytes::from(frame)
+}
+
+/// A decoded protobuf field: varints and fixed-width numbers as integers, the rest as bytes
+enum Field<'a> {
+    Number(u64),
+    Bytes(&'a [u8]),
+}
+
+fn read_varint(data: &[u8], position: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *data.get(*position)?;
+        *position += 1;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Decodes the top-level fields of a protobuf message
+fn fields(data: &[u8]) -> Vec<(u64, Field<'_>)> {
+    let mut fields = Vec::new();
+    let mut position = 0;
+    while position < data.len() {
+        let key = match read_varint(data, &mut position) {
+            Some(key) => key,
+            None => break,
+        };
+        let field = match key & 7 {
+            0 => read_varint(data, &mut position).map(Field::Number),
+            1 => data.get(position..position + 8).map(|bytes| {
+                position += 8;
+                Field::Number(u64::from_le_bytes(bytes.try_into().unwrap()))
+            }),
+            2 => read_varint(data, &mut position).and_then(|length| {
+                let bytes = data.get(position..position + length as usize)?;
+                position += length as usize;
+                Some(Field::Bytes(bytes))
+            }),
+            5 => data.get(position..position + 4).map(|bytes| {
+                position += 4;
+                Field::Number(u64::from(u32::from_le_bytes(bytes.try_into().unwrap())))
+            }),
+            _ => None,
+        };
+        match field {
+            Some(field) => fields.push((key >> 3, field)),
+            None => break,
+        }
+    }
+    fields
+}
+
+fn bytes_of(data: &[u8], number: u64) -> Option<&[u8]> {
+    fields(data).into_iter().find_map(|(n, field)| match field {
+        Field::Bytes(bytes) if n == number => Some(bytes),
+        _ => None,
+    })
+}
+
+fn string_field(data: &[u8], number: u64) -> Option<String> {
+    bytes_of(data, number).map(|bytes| String::from_utf8_lossy(bytes).into_owned())
+}
+
+fn number_field(data: &[u8], number: u64) -> Option<u64> {
+    fields(data).into_iter().find_map(|(n, field)| match field {
+        Field::Number(value) if n == number => Some(value),
+        _ => None,
+    })
+}
+
+fn bytes_field(number: u64, data: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(data.len() + 6);
+    for value in [(number << 3) | 2, data.len() as u64] {
+        let mut value = value;
+        while value >= 0x80 {
+            encoded.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        encoded.push(value as u8);
+    }
+    encoded.extend_from_slice(data);
+    encoded
+}
+
+/// Seconds of a google.protobuf.Timestamp
+fn timestamp(data: &[u8]) -> f64 {
+    number_field(data, 1).unwrap_or(0) as f64 + number_field(data, 2).unwrap_or(0) as f64 / 1e9
+}
+
+/// Renders `moby.buildkit.trace` messages like `--progress=plain`
+ # [derive(Default)]
+pub struct TraceDisplay {
+    numbers: HashMap<String, usize>,
+    started: HashMap<String, f64>,
+    finished: Vec<String>,
+}
+
+impl TraceDisplay {
+    /// Lines to print for one base64 encoded StatusResponse
+    pub fn lines(&mut self, aux: &str) -> Vec<String> {
+        let data = match STANDARD.decode(aux) {
+            Ok(data) => data,
+            Err(_) => return Vec::new(),
+        };
+        let mut lines = Vec::new();
+        for (number, field) in fields(&data) {
+            let message = match field {
+                Field::Bytes(message) => message,
+                Field::Number(_) => continue,
+            };
+            match number {
+                1 => self.vertex(message, &mut lines),
+                2 => {
+                    // VertexStatus: only finished sub-tasks, such as pulled layers
+                    let vertex = string_field(message, 2).unwrap_or_default();
+                    if bytes_of(message, 8).is_some() {
+                        let id = string_field(message, 1).unwrap_or_default();
+                        let step = self.number(&vertex);
+                        lines.push(format!("#{} {} done", step, id));
+                    }
+                }
+                3 => {
+                    let vertex = string_field(message, 1).unwrap_or_default();
+                    let step = self.number(&vertex);
+                    let text = String::from_utf8_lossy(bytes_of(message, 4).unwrap_or_default()).into_owned();
+                    let elapsed = bytes_of(message, 2).map(timestamp).zip(self.started.get(&vertex)).map(|(now, started)| now - started);
+                    for line in text.lines() {
+                        match elapsed {
+                            Some(elapsed) => lines.push(format!("#{} {:.3} {}", step, elapsed.max(0.0), line)),
+                            None => lines.push(format!("#{} {}", step, line)),
+                        }
+                    }
+                }
+                4 => {
+                    let short = string_field(message, 3).unwrap_or_default();
+                    lines.push(format!("WARNING: {}", short));
+                }
+                _ => {}
+            }
+        }
+        lines
+    }
+
+    fn number(&mut self, digest: &str) -> usize {
+        let next = self.numbers.len() + 1;
+        *self.numbers.entry(digest.to_string()).or_insert(next)
+    }
+
+    fn vertex(&mut self, message: &[u8], lines: &mut Vec<String>) {
+        let digest = string_field(message, 1).unwrap_or_default();
+        if self.finished.contains(&digest) {
+            return;
+        }
+        let is_new = !self.numbers.contains_key(&digest);
+        let step = self.number(&digest);
+        if is_new {
+            lines.push(format!("#{} {}", step, string_field(message, 3).unwrap_or_default()));
+        }
+        let started = bytes_of(message, 5).map(timestamp);
+        if let Some(started) = started {
+            self.started.entry(digest.clone()).or_insert(started);
+        }
+        let error = string_field(message, 7).unwrap_or_default();
+        if number_field(message, 4) == Some(1) {
+            lines.push(format!("#{} CACHED", step));
+            self.finished.push(digest);
+        }
+        else if !error.is_empty() {
+            lines.push(format!("#{} ERROR: {}", step, error));
+            self.finished.push(digest);
+        }
+        else if let Some(completed) = bytes_of(message, 6).map(timestamp) {
+            let took = completed - self.started.get(&digest).copied().unwrap_or(completed);
+            lines.push(format!("#{} DONE {:.1}s", step, took.max(0.0)));
+            self.finished.push(digest);
+        }
+    }
+}
@@ -1,4 +1,22 @@
-use futures::StreamExt;
+use crate::{
+    engine::{
+        self,
+        Engine
+    },
+    signal,
+    stats,
+    template,
+    term,
+    units
+};
+use futures::{
+    future::join_all,
+    StreamExt
+};
+use hyper::{
+    upgrade::Upgraded,
+    Body
+};
 use shiplift::{
     tty::TtyChunk,
     LogsOptions,
@@ -13,12 +31,20 @@ use clap::{
     Parser,
     Subcommand
 };
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::io::{
+    AsyncReadExt,
+    AsyncWriteExt
+};
 use std::{
+    collections::HashMap,
     fs::OpenOptions,
+    future::Future,
     io::Write,
+    process,
     time::Duration
 };
-use std::{env, str::from_utf8};
 
  # [derive(Subcommand, Debug)]
 pub enum ContainerCommand {
@@ -102,14 +128,24 @@ pub enum ContainerCommand {
 
     /// Display detailed information on one or more containers
     Inspect {
-        /// Container name or ID
-        container: String,
+        /// Container names or IDs
+         # [arg(required = true)]
+        containers: Vec < String > ,
+
+        /// Format output using a custom template
+         # [arg(short, long)]
+        format: Option < String > ,
     },
 
     /// Kill one or more running containers
     Kill {
-        /// Container name or ID
-        container: String,
+        /// Container names or IDs
+         # [arg(required = true)]
+        containers: Vec < String > ,
+
+        /// Signal to send to the container
+         # [arg(short, long, default_value = "KILL")]
+        signal: String,
     },
 
     /// Fetch the logs of a container
@@ -127,18 +163,31 @@ pub enum ContainerCommand {
 
     /// Pause all processes within one or more containers
     Pause {
-        /// Container name or ID
-        container: String,
+        /// Container names or IDs
+         # [arg(required = true)]
+        containers: Vec < String > ,
     },
 
     /// List port mappings or a specific mapping for the container
     Port {
         /// Container name or ID
         container: String,
+
+        /// Only show the mapping of this port
+         # [arg(value_name = "PRIVATE_PORT[/PROTO]")]
+        private_port: Option < String > ,
     },
 
     /// Remove all stopped containers
-    Prune,
+    Prune {
+        /// Provide filter values (e.g. "until=24h", "label!=keep")
+         # [arg(long, value_name = "filter")]
+        filter: Option < Vec < String >> ,
+
+        /// Do not prompt for confirmation
+         # [arg(short, long)]
+        force: bool,
+    },
 
     /// Rename a container
     Rename {
@@ -151,14 +200,32 @@ pub enum ContainerCommand {
 
     /// Restart one or more containers
     Restart {
-        /// Container name or ID
-        container: String,
+        /// Container names or IDs
+         # [arg(required = true)]
+        containers: Vec < String > ,
+
+        /// Seconds to wait for stop before killing the container (default 10)
+         # [arg(short, long)]
+        time: Option < u64 > ,
     },
 
     /// Remove one or more containers
     Rm {
-        /// Container name or ID
-        container: String,
+        /// Container names or IDs
+         # [arg(required = true)]
+        containers: Vec < String > ,
+
+        /// Force the removal of a running container (uses SIGKILL)
+         # [arg(short, long)]
+        force: bool,
+
+        /// Remove the specified link
+         # [arg(short, long)]
+        link: bool,
+
+        /// Remove anonymous volumes associated with the container
+         # [arg(short, long)]
+        volumes: bool,
     },
 
     /// Run a command in a new container
@@ -180,14 +247,39 @@ pub enum ContainerCommand {
 
     /// Start one or more stopped containers
     Start {
-        /// Container name or ID
-        container: String,
+        /// Container names or IDs
+         # [arg(required = true)]
+        containers: Vec < String > ,
+
+        /// Attach STDOUT/STDERR
+         # [arg(short, long)]
+        attach: bool,
+
+        /// Override the key sequence for detaching a container
+         # [arg(long, value_name = "string")]
+        detach_keys: Option < String > ,
+
+        /// Attach container's STDIN
+         # [arg(short, long)]
+        interactive: bool,
     },
 
     /// Display a live stream of container(s) resource usage statistics
     Stats {
-        /// Container name or ID
-        container: String,
+        /// Container names or IDs (default all running containers)
+        containers: Vec < String > ,
+
+        /// Show all containers (default shows just running)
+         # [arg(short, long)]
+        all: bool,
+
+        /// Format output using a custom template, "table", "table TEMPLATE" or "json"
+         # [arg(long)]
+        format: Option < String > ,
+
+        /// Disable streaming stats and only pull the first result
+         # [arg(long)]
+        no_stream: bool,
     },
 
     /// Stop one or more running containers
@@ -204,24 +296,44 @@ pub enum ContainerCommand {
     Top {
         /// Container name or ID
         container: String,
+
+        /// Options passed to ps, e.g. "aux" or "-eo pid,comm". As with docker, everything
+        /// after CONTAINER goes to ps, including flags such as --format
+         # [arg(trailing_var_arg = true, allow_hyphen_values = true, value_name = "ps OPTIONS")]
+        ps_args: Vec < String > ,
+
+        /// Format output using "table" or "json"; must come before CONTAINER
+         # [arg(long)]
+        format: Option < String > ,
     },
 
     /// Unpause all processes within one or more containers
     Unpause {
-        /// Container name or ID
-        container: String,
+        /// Container names or IDs
+         # [arg(required = true)]
+        containers: Vec < String > ,
     },
 
     /// Update configuration of one or more containers
     Update {
-        /// Container name or ID
-        container: String,
+        /// Container names or IDs
+         # [arg(required = true)]
+        containers: Vec < String > ,
+
+        /// Update options
+         # [clap(flatten)]
+        options: UpdateArgOptions,
     },
 
     /// Block until one or more containers stop, then print their exit codes
     Wait {
-        /// Container name or ID
-        container: String,
+        /// Container names or IDs
+         # [arg(required = true)]
+        containers: Vec < String > ,
+
+        /// Wait until the container reaches this state
+         # [arg(long, default_value = "not-running", value_parser = ["not-running", "next-exit", "removed"])]
+        condition: String,
     },
 }
 
@@ -260,9 +372,8 @@ pub async fn handle_container_command(command:  & ContainerCommand) {
         }
         ContainerCommand::Create {
             image,
-            command,
-            arguments,
             options,
+            ..
         }
          => {
             println!("Running container with image: {}", image);
@@ -301,11 +412,13 @@ pub async fn handle_container_command(command:  & ContainerCommand) {
             export(container.to_string()).await;
         }
         ContainerCommand::Start {
-            container
+            containers,
+            attach,
+            detach_keys,
+            interactive
         }
          => {
-            println!("starting container: {}", container);
-            start(container.to_string()).await;
+            start(containers, *attach, detach_keys.as_deref(), *interactive).await;
         }
 
         ContainerCommand::Stop {
@@ -314,10 +427,104 @@ pub async fn handle_container_command(command:  & ContainerCommand) {
         }
          => {
             println!("stopping container: {}", container);
-            stop(container.to_string(), time.clone()).await;
+            stop(container.to_string(), *time).await;
         }
         ContainerCommand::Ls {all} =>{
-            ps(all.clone()).await;
+            ps(*all).await;
+        }
+        ContainerCommand::Inspect {
+            containers,
+            format
+        }
+         => {
+            template::inspect("/containers", containers, format.as_deref()).await;
+        }
+        ContainerCommand::Kill {
+            containers,
+            signal
+        }
+         => {
+            kill(containers, signal).await;
+        }
+        ContainerCommand::Pause {
+            containers
+        }
+         => {
+            pause(containers).await;
+        }
+        ContainerCommand::Unpause {
+            containers
+        }
+         => {
+            unpause(containers).await;
+        }
+        ContainerCommand::Restart {
+            containers,
+            time
+        }
+         => {
+            restart(containers, *time).await;
+        }
+        ContainerCommand::Rm {
+            containers,
+            force,
+            link,
+            volumes
+        }
+         => {
+            rm(containers, *force, *link, *volumes).await;
+        }
+        ContainerCommand::Port {
+            container,
+            private_port
+        }
+         => {
+            port(container, private_port.as_deref()).await;
+        }
+        ContainerCommand::Stats {
+            containers,
+            all,
+            format,
+            no_stream
+        }
+         => {
+            stats::stats(containers, *all, *no_stream, format.as_deref()).await;
+        }
+        ContainerCommand::Top {
+            container,
+            ps_args,
+            format
+        }
+         => {
+            top(container, ps_args, format.as_deref()).await;
+        }
+        ContainerCommand::Update {
+            containers,
+            options
+        }
+         => {
+            update(containers, options).await;
+        }
+        ContainerCommand::Wait {
+            containers,
+            condition
+        }
+         => {
+            wait(containers, condition).await;
+        }
+        ContainerCommand::Prune {
+            filter,
+            force
+        }
+         => {
+            prune(filter.clone().unwrap_or_default(), *force).await;
+        }
+        ContainerCommand::Rename {
+            container,
+            new_name
+        }
+         => {
+            rename(container, new_name).await;
         }
 
         _ => println!("Command not implemented yet."),
@@ -664,6 +871,114 @@ pub struct RunArgOptions {
     workdir: Option < String >
 }
 
+ # [derive(Parser, Debug, Clone)]
+pub struct UpdateArgOptions {
+    /// Block IO weight between 10 and 1000, or 0 to disable
+     # [arg(long, value_name = "uint16")]
+    blkio_weight: Option < u16 > ,
+
+    /// CPU shares (relative weight)
+     # [arg(short = 'c', long)]
+    cpu_shares: Option < i64 > ,
+
+    /// Number of CPUs
+     # [arg(long, value_name = "decimal")]
+    cpus: Option < f64 > ,
+
+    /// CPUs in which to allow execution (0-3, 0,1)
+     # [arg(long)]
+    cpuset_cpus: Option < String > ,
+
+    /// Memory limit
+     # [arg(short = 'm', long)]
+    memory: Option < String > ,
+
+    /// Memory soft limit
+     # [arg(long)]
+    memory_reservation: Option < String > ,
+
+    /// Swap limit equal to memory plus swap: '-1' to enable unlimited swap
+     # [arg(long, allow_hyphen_values = true)]
+    memory_swap: Option < String > ,
+
+    /// Tune container pids limit (-1 for unlimited)
+     # [arg(long, allow_hyphen_values = true)]
+    pids_limit: Option < i64 > ,
+
+    /// Restart policy to apply when a container exits
+     # [arg(long)]
+    restart: Option < String > ,
+}
+
+impl UpdateArgOptions {
+    /// The body of the update request, with only the flags that were given
+    fn resources(&self) -> Result<serde_json::Map<String, Value>, String> {
+        let mut resources = serde_json::Map::new();
+        if let Some(weight) = self.blkio_weight {
+            if weight != 0 && !(10..=1000).contains(&weight) {
+                return Err(format!("invalid argument \"{}\" for \"--blkio-weight\" flag: range is 10 to 1000, or 0 to disable", weight));
+            }
+            resources.insert("BlkioWeight".to_string(), weight.into());
+        }
+        if let Some(shares) = self.cpu_shares {
+            resources.insert("CpuShares".to_string(), shares.into());
+        }
+        if let Some(cpus) = self.cpus {
+            if !cpus.is_finite() || cpus < 0.0 {
+                return Err(format!("invalid argument \"{}\" for \"--cpus\" flag", cpus));
+            }
+            resources.insert("NanoCpus".to_string(), ((cpus * 1e9).round() as i64).into());
+        }
+        if let Some(cpuset) = &self.cpuset_cpus {
+            resources.insert("CpusetCpus".to_string(), cpuset.clone().into());
+        }
+        for (flag, key, value) in [
+            ("memory", "Memory", &self.memory),
+            ("memory-reservation", "MemoryReservation", &self.memory_reservation),
+            ("memory-swap", "MemorySwap", &self.memory_swap),
+        ] {
+            if let Some(value) = value {
+                resources.insert(key.to_string(), memory_bytes(flag, value)?.into());
+            }
+        }
+        if let Some(limit) = self.pids_limit {
+            resources.insert("PidsLimit".to_string(), limit.into());
+        }
+        if let Some(restart) = &self.restart {
+            resources.insert("RestartPolicy".to_string(), restart_policy(restart)?);
+        }
+        Ok(resources)
+    }
+}
+
+/// Parses a memory flag such as `512m`; swap also accepts -1 for unlimited
+fn memory_bytes(flag: &str, value: &str) -> Result<i64, String> {
+    if flag == "memory-swap" && value.trim() == "-1" {
+        return Ok(-1);
+    }
+    units::parse_bytes(value).map_err(|e| format!("invalid argument {:?} for \"--{}\" flag: {}", value, flag, e))
+}
+
+/// Parses `no`, `always`, `unless-stopped` or `on-failure[:max-retries]`
+fn restart_policy(policy: &str) -> Result<Value, String> {
+    let (name, retries) = match policy.split_once(':') {
+        Some((name, retries)) => {
+            let retries: u32 = retries.parse().map_err(|_| format!("maximum retry count must be an integer: {}", retries))?;
+            (name, retries)
+        }
+        None => (policy, 0),
+    };
+    match name {
+        "no" | "always" | "unless-stopped" if retries > 0 => {
+            Err(format!("maximum retry count cannot be used with restart policy '{}'", name))
+        }
+        "no" | "always" | "unless-stopped" | "on-failure" => {
+            Ok(serde_json::json!({"Name": name, "MaximumRetryCount": retries}))
+        }
+        _ => Err(format!("invalid restart policy '{}'", name)),
+    }
+}
+
 pub async fn ps(all: bool) {
     let docker = Docker::new();
     // let ps_filter =ContainerFilter {}   
@@ -688,7 +1003,7 @@ pub async fn ps(all: bool) {
 
     pub async fn create(image:  & str, options: RunArgOptions) {
         let docker = Docker::new();
-        let mut builder:  & mut ContainerOptionsBuilder =  & mut ContainerOptions::builder(image.as_ref());
+        let mut builder:  & mut ContainerOptionsBuilder =  & mut ContainerOptions::builder(image);
         if let Some(name) = options.name {
             builder = builder.name( & name);
         }
@@ -730,29 +1045,30 @@ pub async fn ps(all: bool) {
         while let Some(export_result) = docker.images().get( & id).export().next().await {
             match export_result.and_then( | bytes | export_file.write( & bytes).map_err(Error::from)) {
                 // Ok(n) => println!("copied {} bytes", n),
-                Ok(n) => println!("copied bytes"),
+                Ok(_) => println!("copied bytes"),
                 Err(e) => eprintln!("Error: {}", e),
             }
         }
     }
 
-    pub async fn start(id: String) {
-        let docker = Docker::new();
-        // let container = docker.container::new(&id);
-        // /* match docker.containers().start().await {*/
-        let container = docker
-            .containers()
-            .get( & id);
-        match container.start().await {
-            Ok(()) => println!("successful started container"),
-            Err(e) => eprintln!("Error: {}", e),
-            // _ => println!("unknown"),
+    pub async fn start(containers: &[String], attach: bool, detach_keys: Option<&str>, interactive: bool) {
+        if !attach && !interactive {
+            let docker = &Docker::new();
+            each(containers, |container| async move { docker.containers().get(container).start().await }).await;
+            return;
+        }
+        match containers {
+            [container] => start_attached(container, detach_keys, interactive).await,
+            _ => {
+                eprintln!("Error: you cannot start and attach multiple containers at once");
+                process::exit(1);
+            }
         }
     }
 
     pub async fn stop(id: String, time: Option < u64 > ) {
         let docker = Docker::new();
-        let mut container = docker
+        let container = docker
             .containers()
             .get( & id);
         // match container.stop().await {
@@ -794,36 +1110,6 @@ pub async fn ps(all: bool) {
         }
     }
 
-pub async fn run1(image:  & str, options: RunArgOptions) {
-        let docker = Docker::new();
-        let mut builder:  & mut ContainerOptionsBuilder =  & mut ContainerOptions::builder(image.as_ref());
-        if let Some(name) = options.name {
-            builder = builder.name( & name);
-        }
-        if let Some(workdir) = options.workdir {
-            builder = builder.working_dir( & workdir);
-        }
-        if let Some(volume) = options.volume {
-            let volumes: Vec <  & str >  = volume.iter().map( | s | s.as_str()).collect();
-            // println!("volumes:{:?}", volumes);
-            // let volumes = vec![volume.as_str()];
-            builder = builder.volumes(volumes);
-        }
-        if options.publish_all {
-            builder = builder.publish_all_ports();
-        }
-
-        let container_options = builder.build();
-
-        match docker
-        .containers()
-        .create( & container_options)
-        .await{
-            Ok(info) => println!("{:?}", info),
-            Err(e) => eprintln!("Error: {}", e),
-        }
-    }
-
 async fn exec(id: String, command: Vec<String>) {
     let docker = Docker::new();
     let command  = command.iter().map(|s| s.as_str()).collect();
@@ -837,10 +1123,450 @@ async fn exec(id: String, command: Vec<String>) {
 
     let mut exec_stream = docker.containers().get(&id).exec(&options);
 
-for exec_result in exec_stream.next().await {
+while let Some(exec_result) = exec_stream.next().await {
     match exec_result {
         Ok(chunk) => print_chunk(chunk),
         Err(e) => eprintln!("Error: {}", e),
         }
     }
-}
\ No newline at end of file
+}
+
+/// Runs `action` on every container, printing each one it succeeded for
+async fn each<'a, F, Fut>(containers: &'a [String], action: F)
+where
+    F: Fn(&'a str) -> Fut,
+    Fut: Future<Output = shiplift::Result<()>>,
+{
+    let mut failed = false;
+    for container in containers {
+        match action(container).await {
+            Ok(()) => println!("{}", container),
+            Err(e) => {
+                eprintln!("Error: {}", engine::error_message(&e));
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
+
+pub async fn kill(containers: &[String], signal: &str) {
+    let signal = match signal::parse(signal) {
+        Ok(number) => number.to_string(),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    };
+    let docker = &Docker::new();
+    let signal = signal.as_str();
+    each(containers, |container| async move { docker.containers().get(container).kill(Some(signal)).await }).await;
+}
+
+pub async fn pause(containers: &[String]) {
+    let docker = &Docker::new();
+    each(containers, |container| async move { docker.containers().get(container).pause().await }).await;
+}
+
+pub async fn unpause(containers: &[String]) {
+    let docker = &Docker::new();
+    each(containers, |container| async move { docker.containers().get(container).unpause().await }).await;
+}
+
+pub async fn restart(containers: &[String], time: Option<u64>) {
+    let docker = &Docker::new();
+    let wait = time.map(Duration::from_secs);
+    each(containers, |container| async move { docker.containers().get(container).restart(wait).await }).await;
+}
+
+pub async fn rename(container: &str, new_name: &str) {
+    let docker = Docker::new();
+    if let Err(e) = docker.containers().get(container).rename(new_name).await {
+        eprintln!("Error: {}", engine::error_message(&e));
+        process::exit(1);
+    }
+}
+
+pub async fn rm(containers: &[String], force: bool, link: bool, volumes: bool) {
+    let engine = &Engine::new();
+    let params = [("force", force), ("link", link), ("v", volumes)];
+    each(containers, |container| async move {
+        // Links are written as /parent/alias
+        let endpoint = format!("/containers/{}", container.trim_matches('/'));
+        let params = params.iter().filter(|(_, set)| *set).map(|(name, _)| (*name, "1".to_string()));
+        engine.delete(&engine::query(&endpoint, params)).await
+    })
+    .await;
+}
+
+ # [derive(Deserialize, Debug)]
+ # [serde(rename_all = "PascalCase")]
+struct PruneReport {
+    containers_deleted: Option<Vec<String>>,
+    space_reclaimed: u64,
+}
+
+pub async fn prune(filter: Vec<String>, force: bool) {
+    let filters = match engine::filters(&filter) {
+        Ok(filters) => filters,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    };
+    if !force && !term::confirm("WARNING! This will remove all stopped containers.") {
+        return;
+    }
+
+    let engine = Engine::new();
+    let report: PruneReport = match engine.post_json(&engine::query("/containers/prune", vec![("filters", filters)]), None).await {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("Error: {}", engine::error_message(&e));
+            process::exit(1);
+        }
+    };
+    let deleted = report.containers_deleted.unwrap_or_default();
+    if !deleted.is_empty() {
+        println!("Deleted Containers:");
+        for id in deleted {
+            println!("{}", id);
+        }
+        println!();
+    }
+    println!("Total reclaimed space: {}", units::human_size(report.space_reclaimed as f64));
+}
+
+ # [derive(Deserialize, Debug)]
+ # [serde(rename_all = "PascalCase")]
+struct PortDetails {
+    network_settings: NetworkSettings,
+}
+
+ # [derive(Deserialize, Debug)]
+ # [serde(rename_all = "PascalCase")]
+struct NetworkSettings {
+    ports: Option<HashMap<String, Option<Vec<PortBinding>>>>,
+}
+
+ # [derive(Deserialize, Debug)]
+ # [serde(rename_all = "PascalCase")]
+struct PortBinding {
+    host_ip: String,
+    host_port: String,
+}
+
+impl PortBinding {
+    fn address(&self) -> String {
+        if self.host_ip.contains(':') {
+            format!("[{}]:{}", self.host_ip, self.host_port)
+        }
+        else {
+            format!("{}:{}", self.host_ip, self.host_port)
+        }
+    }
+}
+
+/// Orders `443/tcp` after `80/udp`, by number then protocol
+fn port_key(port: &str) -> (u16, String) {
+    let (number, proto) = port.split_once('/').unwrap_or((port, "tcp"));
+    (number.parse().unwrap_or(0), proto.to_string())
+}
+
+pub async fn port(container: &str, private_port: Option<&str>) {
+    let engine = Engine::new();
+    let details: PortDetails = match engine.get_json(&format!("/containers/{}/json", container)).await {
+        Ok(details) => details,
+        Err(e) => {
+            eprintln!("Error: {}", engine::error_message(&e));
+            process::exit(1);
+        }
+    };
+    let ports = details.network_settings.ports.unwrap_or_default();
+
+    if let Some(private_port) = private_port {
+        let (number, proto) = private_port.split_once('/').unwrap_or((private_port, "tcp"));
+        if number.parse::<u16>().is_err() {
+            eprintln!("Error: invalid port {:?}", private_port);
+            process::exit(1);
+        }
+        let key = format!("{}/{}", number, proto.to_lowercase());
+        match ports.get(&key) {
+            Some(Some(bindings)) if !bindings.is_empty() => {
+                for binding in bindings {
+                    println!("{}", binding.address());
+                }
+            }
+            _ => {
+                eprintln!("Error: No public port '{}' published for {}", key, container);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let mut published: Vec<(&String, &Vec<PortBinding>)> = ports
+        .iter()
+        .filter_map(|(port, bindings)| bindings.as_ref().map(|bindings| (port, bindings)))
+        .collect();
+    published.sort_by_key(|(port, _)| port_key(port));
+    for (port, bindings) in published {
+        for binding in bindings {
+            println!("{} -> {}", port, binding.address());
+        }
+    }
+}
+
+ # [derive(Deserialize, Debug)]
+ # [serde(rename_all = "PascalCase")]
+struct Processes {
+    titles: Vec<String>,
+    processes: Option<Vec<Vec<String>>>,
+}
+
+pub async fn top(container: &str, ps_args: &[String], format: Option<&str>) {
+    if !matches!(format, None | Some("table") | Some("json")) {
+        eprintln!("Error: unsupported format: {}", format.unwrap_or_default());
+        process::exit(1);
+    }
+    let mut params = Vec::new();
+    if !ps_args.is_empty() {
+        params.push(("ps_args", ps_args.join(" ")));
+    }
+    let engine = Engine::new();
+    let top: Processes = match engine.get_json(&engine::query(&format!("/containers/{}/top", container), params)).await {
+        Ok(top) => top,
+        Err(e) => {
+            eprintln!("Error: {}", engine::error_message(&e));
+            process::exit(1);
+        }
+    };
+    let processes = top.processes.unwrap_or_default();
+
+    if format == Some("json") {
+        for process in processes {
+            let line: serde_json::Map<String, Value> =
+                top.titles.iter().cloned().zip(process.into_iter().map(Value::String)).collect();
+            println!("{}", Value::Object(line));
+        }
+        return;
+    }
+    let titles: Vec<&str> = top.titles.iter().map(String::as_str).collect();
+    term::print_table(&titles, &processes);
+}
+
+ # [derive(Deserialize, Debug)]
+ # [serde(rename_all = "PascalCase")]
+struct UpdateReport {
+    warnings: Option<Vec<String>>,
+}
+
+pub async fn update(containers: &[String], options: &UpdateArgOptions) {
+    let resources = match options.resources() {
+        Ok(resources) if resources.is_empty() => {
+            eprintln!("Error: you must provide one or more flags when using this command");
+            process::exit(1);
+        }
+        Ok(resources) => Value::Object(resources).to_string(),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    };
+    let engine = &Engine::new();
+    let resources = resources.as_str();
+    each(containers, |container| async move {
+        let body = Some((Body::from(resources.to_string()), mime::APPLICATION_JSON));
+        let report: UpdateReport = engine.post_json(&format!("/containers/{}/update", container), body).await?;
+        for warning in report.warnings.unwrap_or_default() {
+            eprintln!("WARNING: {}", warning);
+        }
+        Ok(())
+    })
+    .await;
+}
+
+ # [derive(Deserialize, Debug)]
+ # [serde(rename_all = "PascalCase")]
+struct WaitResponse {
+    status_code: i64,
+    error: Option<WaitError>,
+}
+
+ # [derive(Deserialize, Debug)]
+ # [serde(rename_all = "PascalCase")]
+struct WaitError {
+    message: String,
+}
+
+/// Exits with the first container's exit code, or 1 if any wait failed
+pub async fn wait(containers: &[String], condition: &str) {
+    let engine = &Engine::new();
+    // Every wait is registered up front so that next-exit can't miss an exit
+    let results = join_all(containers.iter().map(|container| async move {
+        let endpoint = engine::query(&format!("/containers/{}/wait", container), vec![("condition", condition.to_string())]);
+        engine.post_json::<WaitResponse>(&endpoint, None).await
+    }))
+    .await;
+
+    let mut code = None;
+    let mut failed = false;
+    for result in results {
+        match result {
+            Ok(WaitResponse { error: Some(error), .. }) => {
+                eprintln!("Error: {}", error.message);
+                failed = true;
+            }
+            Ok(response) => {
+                println!("{}", response.status_code);
+                code.get_or_insert(response.status_code);
+            }
+            Err(e) => {
+                eprintln!("Error: {}", engine::error_message(&e));
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+    process::exit(code.unwrap_or(0) as i32);
+}
+
+ # [derive(Deserialize, Debug)]
+ # [serde(rename_all = "PascalCase")]
+struct AttachDetails {
+    config: AttachConfig,
+    host_config: AttachHostConfig,
+    state: AttachState,
+}
+
+ # [derive(Deserialize, Debug)]
+ # [serde(rename_all = "PascalCase")]
+struct AttachConfig {
+    tty: bool,
+}
+
+ # [derive(Deserialize, Debug)]
+ # [serde(rename_all = "PascalCase")]
+struct AttachHostConfig {
+     # [serde(default)]
+    auto_remove: bool,
+}
+
+ # [derive(Deserialize, Debug)]
+ # [serde(rename_all = "PascalCase")]
+struct AttachState {
+    running: bool,
+    exit_code: i64,
+}
+
+/// Starts a container with its output attached, then exits with its exit code
+async fn start_attached(container: &str, detach_keys: Option<&str>, interactive: bool) {
+    let fail = |e: Error| -> ! {
+        eprintln!("Error: {}", engine::error_message(&e));
+        process::exit(1);
+    };
+    let engine = Engine::new();
+    let details: AttachDetails = engine
+        .get_json(&format!("/containers/{}/json", container))
+        .await
+        .unwrap_or_else(|e| fail(e));
+
+    let mut params = vec![("stream", "1".to_string()), ("stdout", "1".to_string()), ("stderr", "1".to_string())];
+    if interactive {
+        params.push(("stdin", "1".to_string()));
+    }
+    if let Some(keys) = detach_keys {
+        params.push(("detachKeys", keys.to_string()));
+    }
+    let endpoint = engine::query(&format!("/containers/{}/attach", container), params);
+    let connection = engine.upgrade(&endpoint, "tcp", Vec::new()).await.unwrap_or_else(|e| fail(e));
+
+    // An --rm container may be gone before it can be inspected again
+    let removed = details.host_config.auto_remove.then(|| {
+        let endpoint = engine::query(&format!("/containers/{}/wait", container), vec![("condition", "removed".to_string())]);
+        tokio::spawn(async move { Engine::new().post_json::<WaitResponse>(&endpoint, None).await })
+    });
+
+    let raw_mode = if interactive && details.config.tty {
+        term::RawMode::enable()
+    }
+    else {
+        None
+    };
+    if let Err(e) = Docker::new().containers().get(container).start().await {
+        drop(raw_mode);
+        fail(e);
+    }
+    let copied = copy_attached(connection, details.config.tty, interactive).await;
+    drop(raw_mode);
+    if let Err(e) = copied {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+
+    // Exit with the container, or quietly if the detach keys were used
+    let code = match engine.get_json::<AttachDetails>(&format!("/containers/{}/json", container)).await {
+        Ok(details) if details.state.running => 0,
+        Ok(details) => details.state.exit_code,
+        Err(e) => match removed {
+            Some(wait) => match wait.await {
+                Ok(Ok(response)) => response.status_code,
+                Ok(Err(e)) => fail(e),
+                Err(_) => 1,
+            },
+            None => fail(e),
+        },
+    };
+    process::exit(code as i32);
+}
+
+/// Relays an attach connection until the container closes its output.
+///
+/// Without a TTY the daemon multiplexes stdout and stderr into frames with an
+/// 8 byte header: the stream in the first byte and the length in the last four.
+async fn copy_attached(connection: Upgraded, tty: bool, interactive: bool) -> std::io::Result<()> {
+    let (mut reader, mut writer) = tokio::io::split(connection);
+    if interactive {
+        tokio::spawn(async move {
+            let _ = tokio::io::copy(&mut tokio::io::stdin(), &mut writer).await;
+            let _ = writer.shutdown().await;
+        });
+    }
+
+    let mut stdout = tokio::io::stdout();
+    let mut stderr = tokio::io::stderr();
+    if tty {
+        let mut buffer = [0u8; 8192];
+        loop {
+            let n = reader.read(&mut buffer).await?;
+            if n == 0 {
+                return Ok(());
+            }
+            stdout.write_all(&buffer[..n]).await?;
+            stdout.flush().await?;
+        }
+    }
+    let mut header = [0u8; 8];
+    loop {
+        match reader.read_exact(&mut header).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        let size = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let mut frame = vec![0u8; size];
+        reader.read_exact(&mut frame).await?;
+        if header[0] == 2 {
+            stderr.write_all(&frame).await?;
+            stderr.flush().await?;
+        }
+        else {
+            stdout.write_all(&frame).await?;
+            stdout.flush().await?;
+        }
+    }
+}
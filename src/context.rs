@@ -0,0 +1,866 @@
+//! Build context packaging.
+//!
+//! The context directory is tarred on the client, the same way the docker CLI
+//! does it, so `.dockerignore` rules are applied before anything is uploaded.
+//! Git repositories are cloned into a temporary directory first; remote and
+//! stdin archives are passed through untouched. Every context is streamed to
+//! the daemon as it is packed or received, so none has to fit in memory.
+
+use crate::{
+    engine,
+    pipe
+};
+use flate2::{
+    write::GzEncoder,
+    Compression
+};
+use futures::{
+    stream,
+    Stream,
+    StreamExt
+};
+use hyper::{
+    body::Bytes,
+    Body
+};
+use std::{
+    fs,
+    io::{
+        self,
+        Read,
+        Write
+    },
+    path::{
+        Path,
+        PathBuf
+    },
+    pin::Pin,
+    process::{
+        self,
+        Command
+    },
+    time::{
+        SystemTime,
+        UNIX_EPOCH
+    },
+};
+use tempfile::TempDir;
+
+/// Chunks of a context tarball
+pub type Chunks = Pin<Box<dyn Stream<Item = io::Result<Bytes>> + Send>>;
+
+/// A context ready to upload to `/build`
+pub struct BuildContext {
+    /// The tarball, produced while it is uploaded
+    pub body: Chunks,
+    /// Value of the `dockerfile` query parameter
+    pub dockerfile: String,
+}
+
+impl BuildContext {
+    /// The request body; `sent` is called with the size of the context once all of it was read
+    pub fn into_body<F>(self, sent: F) -> Body
+    where
+        F: FnOnce(u64) + Send + 'static,
+    {
+        let state = (self.body, 0, Some(sent));
+        Body::wrap_stream(stream::unfold(state, |(mut body, mut size, mut sent)| async move {
+            match body.next().await {
+                Some(chunk) => {
+                    size += chunk.as_ref().map_or(0, |chunk| chunk.len() as u64);
+                    Some((chunk, (body, size, sent)))
+                }
+                None => {
+                    if let Some(sent) = sent.take() {
+                        sent(size);
+                    }
+                    None
+                }
+            }
+        }))
+    }
+}
+
+/// Resolves the PATH | URL | - argument of a build and packs the context.
+///
+/// `file` is the `--file` flag; `-` reads the Dockerfile from stdin.
+pub async fn prepare(path_or_url: &str, file: Option<&str>, compress: bool, quiet: bool) -> Result<BuildContext, String> {
+    let stdin_dockerfile = match file {
+        Some("-") if path_or_url == "-" => {
+            return Err("invalid argument: can't use stdin for both build context and dockerfile".to_string());
+        }
+        Some("-") => Some(read_stdin()?),
+        _ => None,
+    };
+
+    if path_or_url == "-" {
+        return from_stream(Box::pin(engine::read_chunks(tokio::io::stdin())), file).await;
+    }
+    if is_remote(path_or_url) && !is_git_url(path_or_url) {
+        if stdin_dockerfile.is_some() {
+            return Err("invalid argument: can't use a Dockerfile from stdin with a remote archive".to_string());
+        }
+        return from_stream(download(path_or_url, quiet).await?, file).await;
+    }
+    // Cloning and reading the context block on git and the file system; keep them
+    // off the runtime so concurrent builds, such as bake targets, keep making progress
+    let path_or_url = path_or_url.to_string();
+    let file = file.map(str::to_string);
+    let (dockerfile, packer) = tokio::task::spawn_blocking(move || from_local(&path_or_url, file.as_deref(), stdin_dockerfile, compress))
+        .await
+        .map_err(|e| format!("failed to prepare build context: {}", e))??;
+    Ok(BuildContext { body: Box::pin(pipe::produce(packer)), dockerfile })
+}
+
+/// Writes a context tarball
+type Packer = Box<dyn FnOnce(&mut dyn Write) -> io::Result<()> + Send>;
+
+/// Resolves a local directory or a git checkout, returning the Dockerfile name and what packs the context
+fn from_local(path_or_url: &str, file: Option<&str>, stdin_dockerfile: Option<Vec<u8>>, compress: bool) -> Result<(String, Packer), String> {
+    if is_git_url(path_or_url) {
+        let (checkout, context) = clone_git(path_or_url)?;
+        // A relative --file is looked up inside the repository
+        let file = file.filter(|f| *f != "-").map(|f| context.join(f).to_string_lossy().to_string());
+        let (dockerfile, pack) = from_dir(context, file.as_deref(), stdin_dockerfile, compress)?;
+        // The checkout is removed once it has been packed
+        let pack: Packer = Box::new(move |out| {
+            let packed = pack(out);
+            drop(checkout);
+            packed
+        });
+        return Ok((dockerfile, pack));
+    }
+    from_dir(PathBuf::from(path_or_url), file, stdin_dockerfile, compress)
+}
+
+fn from_dir(context: PathBuf, file: Option<&str>, stdin_dockerfile: Option<Vec<u8>>, compress: bool) -> Result<(String, Packer), String> {
+    let dockerfile = match stdin_dockerfile {
+        Some(contents) => {
+            if !context.is_dir() {
+                return Err(format!("unable to prepare context: path {:?} not found", context.display().to_string()));
+            }
+            Dockerfile {
+                name: random_dockerfile_name(),
+                contents: Some(contents),
+                path: None,
+            }
+        }
+        None => locate_dockerfile(&context.to_string_lossy(), file)?,
+    };
+    let mut matcher = match read_ignore_file(&context, &dockerfile) {
+        Ok(lines) => IgnoreMatcher::new(&lines),
+        Err(e) => return Err(format!("failed to read .dockerignore: {}", e)),
+    };
+    // The daemon needs these even when .dockerignore excludes them
+    matcher.keep(&dockerfile.name);
+    matcher.keep(".dockerignore");
+    let name = dockerfile.name.clone();
+    let pack: Packer = Box::new(move |out| {
+        pack(&context, &matcher, &dockerfile, compress, out)
+            .map_err(|e| io::Error::new(e.kind(), format!("failed to read build context {}: {}", context.display(), e)))
+    });
+    Ok((name, pack))
+}
+
+/// An archive is passed on as it arrives; anything else is taken to be a lone Dockerfile
+async fn from_stream(chunks: Chunks, file: Option<&str>) -> Result<BuildContext, String> {
+    let mut chunks = chunks.fuse();
+    let failed = |e: io::Error| format!("failed to read build context: {}", e);
+    // Enough to recognize any of the archive formats
+    let mut head = Vec::new();
+    while head.len() < 262 {
+        match chunks.next().await {
+            Some(chunk) => head.extend_from_slice(&chunk.map_err(failed)?),
+            None => break,
+        }
+    }
+    if !is_archive(&head) {
+        while let Some(chunk) = chunks.next().await {
+            head.extend_from_slice(&chunk.map_err(failed)?);
+        }
+        let tarball = dockerfile_tar(&head)?;
+        return Ok(BuildContext {
+            body: Box::pin(stream::once(async move { Ok(Bytes::from(tarball)) })),
+            dockerfile: "Dockerfile".to_string(),
+        });
+    }
+    Ok(BuildContext {
+        body: Box::pin(stream::once(async move { Ok(Bytes::from(head)) }).chain(chunks)),
+        dockerfile: file.unwrap_or("Dockerfile").to_string(),
+    })
+}
+
+/// A context holding nothing but a Dockerfile
+fn dockerfile_tar(contents: &[u8]) -> Result<Vec<u8>, String> {
+    let mut archive = tar::Builder::new(Vec::new());
+    let mut header = tar::Header::new_gnu();
+    header.set_size(contents.len() as u64);
+    header.set_mode(0o644);
+    archive
+        .append_data(&mut header, "Dockerfile", contents)
+        .and_then(|_| archive.into_inner())
+        .map_err(|e| format!("failed to create build context: {}", e))
+}
+
+fn read_stdin() -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    io::stdin().read_to_end(&mut bytes).map_err(|e| format!("failed to read from stdin: {}", e))?;
+    Ok(bytes)
+}
+
+/// Tar, or a tar compressed with gzip, bzip2 or xz
+fn is_archive(bytes: &[u8]) -> bool {
+    bytes.starts_with(&[0x1f, 0x8b])
+        || bytes.starts_with(b"BZh")
+        || bytes.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00])
+        || bytes.get(257..262) == Some(b"ustar".as_slice())
+}
+
+/// Whether a context is fetched rather than read from a local directory
+pub fn is_remote(path_or_url: &str) -> bool {
+    is_git_url(path_or_url) || path_or_url.starts_with("http://") || path_or_url.starts_with("https://")
+}
+
+/// Same rules as the docker CLI: git:// and git@ remotes, github.com/ shorthands
+/// and URLs whose path ends in `.git`
+fn is_git_url(url: &str) -> bool {
+    if url.starts_with("git://") || url.starts_with("git@") || url.starts_with("github.com/") {
+        return true;
+    }
+    let has_scheme = ["http://", "https://", "ssh://", "file://"].iter().any(|scheme| url.starts_with(scheme));
+    let repository = url.split('#').next().unwrap_or(url);
+    has_scheme && repository.ends_with(".git")
+}
+
+/// Fetches `url#ref:subdir` into a temporary directory.
+///
+/// Returns the checkout, which is removed when dropped, and the context directory inside it.
+fn clone_git(url: &str) -> Result<(TempDir, PathBuf), String> {
+    let (repository, fragment) = url.split_once('#').unwrap_or((url, ""));
+    let (reference, subdir) = fragment.split_once(':').unwrap_or((fragment, ""));
+    let reference = if reference.is_empty() { "HEAD" } else { reference };
+    let repository = match repository.strip_prefix("github.com/") {
+        Some(rest) => format!("https://github.com/{}", rest),
+        None => repository.to_string(),
+    };
+
+    let checkout = tempfile::Builder::new()
+        .prefix("rocker-build-git")
+        .tempdir()
+        .map_err(|e| format!("unable to create temporary directory: {}", e))?;
+    let root = checkout.path();
+    git(root, &["init", "--quiet"])?;
+    git(root, &["remote", "add", "origin", &repository])?;
+    // Not every transport supports shallow fetches, e.g. dumb http
+    if git(root, &["fetch", "--quiet", "--depth", "1", "origin", "--", reference]).is_err() {
+        git(root, &["fetch", "--quiet", "origin", "--", reference])?;
+    }
+    git(root, &["checkout", "--quiet", "FETCH_HEAD"])?;
+    git(root, &["submodule", "update", "--quiet", "--init", "--recursive", "--depth", "1"])?;
+    // Only the working tree is part of the context
+    fs::remove_dir_all(root.join(".git")).map_err(|e| format!("unable to clean up git checkout: {}", e))?;
+
+    let root = fs::canonicalize(root).map_err(|e| e.to_string())?;
+    let context = fs::canonicalize(root.join(subdir))
+        .map_err(|e| format!("error setting git context, subdirectory {:?} not found: {}", subdir, e))?;
+    if !context.starts_with(&root) || !context.is_dir() {
+        return Err(format!("error setting git context, not a directory inside the repository: {}", subdir));
+    }
+    Ok((checkout, context))
+}
+
+fn git(dir: &Path, args: &[&str]) -> Result<(), String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .map_err(|e| format!("unable to run git: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "error fetching git context: git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+async fn download(url: &str, quiet: bool) -> Result<Chunks, String> {
+    if !quiet {
+        println!("Downloading build context from remote url: {}", url);
+    }
+    let response = reqwest::get(url).await.map_err(|e| format!("unable to download remote context {}: {}", url, e))?;
+    if !response.status().is_success() {
+        return Err(format!("failed to GET {} with status {}", url, response.status()));
+    }
+    let url = url.to_string();
+    Ok(Box::pin(response.bytes_stream().map(move |chunk| {
+        chunk.map_err(|e| io::Error::other(format!("unable to download remote context {}: {}", url, e)))
+    })))
+}
+
+/// The Dockerfile of a build, as seen from inside the context
+pub struct Dockerfile {
+    /// Path of the Dockerfile inside the context tarball
+    pub name: String,
+    /// Contents to add to the tarball when the Dockerfile is not part of the context
+    pub contents: Option<Vec<u8>>,
+    /// Location on disk, used to find a Dockerfile-specific ignore file
+    pub path: Option<PathBuf>,
+}
+
+/// Resolves `--file` against the context directory
+fn locate_dockerfile(context: &str, file: Option<&str>) -> Result<Dockerfile, String> {
+    let context_path = fs::canonicalize(context).map_err(|e| format!("unable to prepare context: {}: {}", context, e))?;
+    let file = match file {
+        Some(file) => PathBuf::from(file),
+        None => context_path.join("Dockerfile"),
+    };
+    let path = fs::canonicalize(&file).map_err(|e| format!("cannot locate specified Dockerfile: {}: {}", file.display(), e))?;
+    match path.strip_prefix(&context_path) {
+        Ok(relative) => Ok(Dockerfile {
+            name: relative.to_string_lossy().replace('\\', "/"),
+            contents: None,
+            path: Some(path),
+        }),
+        Err(_) => {
+            let contents = fs::read(&path).map_err(|e| format!("{}: {}", file.display(), e))?;
+            Ok(Dockerfile {
+                name: random_dockerfile_name(),
+                contents: Some(contents),
+                path: Some(path),
+            })
+        }
+    }
+}
+
+/// A name for a Dockerfile injected into the context, unlikely to clash with real files
+fn random_dockerfile_name() -> String {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+    format!(".dockerfile.{:x}{:x}", process::id(), nanos)
+}
+
+/// Reads the ignore rules for a build.
+///
+/// A `<Dockerfile>.dockerignore` next to the Dockerfile takes precedence over the
+/// `.dockerignore` at the root of the context.
+fn read_ignore_file(context: &Path, dockerfile: &Dockerfile) -> io::Result<Vec<String>> {
+    let mut candidates = Vec::new();
+    if let Some(path) = &dockerfile.path {
+        let mut specific = path.clone().into_os_string();
+        specific.push(".dockerignore");
+        candidates.push(PathBuf::from(specific));
+    }
+    candidates.push(context.join(".dockerignore"));
+    for candidate in candidates {
+        match fs::read_to_string(&candidate) {
+            Ok(contents) => return Ok(contents.lines().map(str::to_string).collect()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(Vec::new())
+}
+
+struct Pattern {
+    glob: String,
+    exclusion: bool,
+}
+
+/// `.dockerignore` rules; the last pattern matching a path decides whether it is excluded
+pub struct IgnoreMatcher {
+    patterns: Vec<Pattern>,
+}
+
+impl IgnoreMatcher {
+    pub fn new(lines: &[String]) -> IgnoreMatcher {
+        let mut patterns = Vec::new();
+        for line in lines {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (exclusion, glob) = match line.strip_prefix('!') {
+                Some(glob) => (true, glob.trim()),
+                None => (false, line),
+            };
+            let glob = clean_path(glob);
+            if glob.is_empty() {
+                continue;
+            }
+            patterns.push(Pattern { glob, exclusion });
+        }
+        IgnoreMatcher { patterns }
+    }
+
+    /// Makes sure a path is sent even if a pattern excludes it
+    pub fn keep(&mut self, path: &str) {
+        self.patterns.push(Pattern {
+            glob: escape_glob(&clean_path(path)),
+            exclusion: true,
+        });
+    }
+
+    /// Whether a slash separated path, relative to the context, is ignored
+    pub fn excluded(&self, path: &str) -> bool {
+        let parents: Vec<&str> = path.match_indices('/').map(|(i, _)| &path[..i]).collect();
+        let mut excluded = false;
+        for pattern in &self.patterns {
+            let matched = glob_match(pattern.glob.as_bytes(), path.as_bytes())
+                || parents.iter().any(|parent| glob_match(pattern.glob.as_bytes(), parent.as_bytes()));
+            if matched {
+                excluded = !pattern.exclusion;
+            }
+        }
+        excluded
+    }
+
+    fn has_exclusions(&self) -> bool {
+        self.patterns.iter().any(|p| p.exclusion)
+    }
+}
+
+/// Normalizes a pattern or path like Go's `filepath.Clean`, without a leading slash
+fn clean_path(path: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            _ => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+fn escape_glob(path: &str) -> String {
+    let mut escaped = String::new();
+    for c in path.chars() {
+        if "*?[\\".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Matches a whole path against a glob where `*`, `?` and `[...]` stay within one
+/// path segment and `**` spans any number of segments
+fn glob_match(pattern: &[u8], path: &[u8]) -> bool {
+    if pattern.is_empty() {
+        return path.is_empty();
+    }
+    match pattern[0] {
+        b'*' if pattern.get(1) == Some(&b'*') => {
+            let rest = &pattern[2..];
+            if let Some(after_slash) = rest.strip_prefix(b"/") {
+                // `**/` matches zero or more leading directories
+                glob_match(after_slash, path)
+                    || (0..path.len()).any(|i| path[i] == b'/' && glob_match(after_slash, &path[i + 1..]))
+            }
+            else {
+                (0..=path.len()).any(|i| glob_match(rest, &path[i..]))
+            }
+        }
+        b'*' => {
+            let rest = &pattern[1..];
+            let segment_end = path.iter().position(|&c| c == b'/').unwrap_or(path.len());
+            (0..=segment_end).any(|i| glob_match(rest, &path[i..]))
+        }
+        b'?' => !path.is_empty() && path[0] != b'/' && glob_match(&pattern[1..], &path[1..]),
+        b'[' => {
+            if path.is_empty() || path[0] == b'/' {
+                return false;
+            }
+            match match_class(&pattern[1..], path[0]) {
+                Some((matched, rest)) => matched && glob_match(rest, &path[1..]),
+                None => false,
+            }
+        }
+        b'\\' if pattern.len() > 1 => !path.is_empty() && path[0] == pattern[1] && glob_match(&pattern[2..], &path[1..]),
+        c => !path.is_empty() && path[0] == c && glob_match(&pattern[1..], &path[1..]),
+    }
+}
+
+/// Matches one byte against a `[...]` class, returning the pattern after the class
+fn match_class(class: &[u8], c: u8) -> Option<(bool, &[u8])> {
+    let (negated, mut i) = match class.first() {
+        Some(b'^') | Some(b'!') => (true, 1),
+        _ => (false, 0),
+    };
+    let mut matched = false;
+    let mut first = true;
+    while i < class.len() {
+        if class[i] == b']' && !first {
+            return Some((matched != negated, &class[i + 1..]));
+        }
+        first = false;
+        let mut low = class[i];
+        if low == b'\\' {
+            i += 1;
+            low = *class.get(i)?;
+        }
+        let mut high = low;
+        if class.get(i + 1) == Some(&b'-') && class.get(i + 2).is_some_and(|&h| h != b']') {
+            i += 2;
+            high = class[i];
+            if high == b'\\' {
+                i += 1;
+                high = *class.get(i)?;
+            }
+        }
+        if low <= c && c <= high {
+            matched = true;
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Tars the context directory into `out`, skipping ignored paths, and optionally gzips it
+fn pack(context: &Path, matcher: &IgnoreMatcher, dockerfile: &Dockerfile, compress: bool, out: &mut dyn Write) -> io::Result<()> {
+    if compress {
+        let mut encoder = GzEncoder::new(out, Compression::default());
+        write_tar(context, matcher, dockerfile, &mut encoder)?;
+        encoder.finish().map(drop)
+    }
+    else {
+        write_tar(context, matcher, dockerfile, out)
+    }
+}
+
+fn write_tar(context: &Path, matcher: &IgnoreMatcher, dockerfile: &Dockerfile, out: &mut dyn Write) -> io::Result<()> {
+    let mut archive = tar::Builder::new(out);
+    archive.follow_symlinks(false);
+    append_dir(&mut archive, context, "", matcher)?;
+    if let Some(contents) = &dockerfile.contents {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        archive.append_data(&mut header, &dockerfile.name, contents.as_slice())?;
+    }
+    archive.finish()
+}
+
+fn append_dir(archive: &mut tar::Builder<&mut dyn Write>, dir: &Path, prefix: &str, matcher: &IgnoreMatcher) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let relative = format!("{}{}", prefix, name);
+        let file_type = entry.file_type()?;
+        if matcher.excluded(&relative) {
+            // An exception such as `!dir/keep` can still re-include something below
+            if file_type.is_dir() && matcher.has_exclusions() {
+                append_dir(archive, &entry.path(), &format!("{}/", relative), matcher)?;
+            }
+            continue;
+        }
+        archive.append_path_with_name(entry.path(), &relative)?;
+        if file_type.is_dir() {
+            append_dir(archive, &entry.path(), &format!("{}/", relative), matcher)?;
+        }
+    }
+    Ok(())
+}
+
+ # [cfg(test)]
+mod tests {
+    use super::{
+        clone_git,
+        download,
+        from_stream,
+        prepare,
+        Chunks,
+        glob_match,
+        is_git_url,
+        is_remote,
+        IgnoreMatcher
+    };
+    use futures::{
+        stream,
+        StreamExt
+    };
+    use hyper::body::Bytes;
+    use std::{
+        fs,
+        io::{
+            self,
+            Read,
+            Write
+        },
+        net::TcpListener,
+        path::Path,
+        process::Command,
+        thread,
+    };
+
+    async fn collect(mut body: Chunks) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        while let Some(chunk) = body.next().await {
+            bytes.extend_from_slice(&chunk.unwrap());
+        }
+        bytes
+    }
+
+    async fn body_of(path: &str) -> Vec<u8> {
+        collect(prepare(path, None, false, true).await.unwrap().body).await
+    }
+
+    /// File names in an uncompressed tarball
+    fn entries(tarball: &[u8]) -> Vec<String> {
+        tar::Archive::new(tarball)
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().to_string_lossy().to_string())
+            .collect()
+    }
+
+    fn git(dir: &Path, args: &[&str]) {
+        let status = Command::new("git")
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "git {:?} failed", args);
+    }
+
+    /// Serves one HTTP response on a local port and returns its URL
+    fn serve_once(status: &'static str, body: &'static [u8]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request);
+            let head = format!("HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", status, body.len());
+            stream.write_all(head.as_bytes()).unwrap();
+            stream.write_all(body).unwrap();
+        });
+        format!("http://{}/context.tar", address)
+    }
+
+     # [test]
+    fn git_urls() {
+        for url in [
+            "git://example.com/repo",
+            "git@github.com:docker/cli.git",
+            "github.com/docker/cli",
+            "https://example.com/repo.git",
+            "https://example.com/repo.git#main:docs",
+            "ssh://git@example.com/repo.git",
+            "file:///srv/repo.git",
+        ] {
+            assert!(is_git_url(url), "{}", url);
+            assert!(is_remote(url), "{}", url);
+        }
+        for url in ["https://example.com/context.tar", "https://example.com/repo.git.tar", "repo.git", "."] {
+            assert!(!is_git_url(url), "{}", url);
+        }
+        assert!(is_remote("https://example.com/context.tar"));
+        assert!(!is_remote("./repo.git"));
+    }
+
+     # [test]
+    fn git_ref_and_subdirectory() {
+        let dir = tempfile::tempdir().unwrap();
+        let repository = dir.path().join("repo.git");
+        fs::create_dir_all(repository.join("app")).unwrap();
+        git(&repository, &["init", "--quiet"]);
+        fs::write(repository.join("app/Dockerfile"), "FROM scratch\n").unwrap();
+        git(&repository, &["add", "."]);
+        git(&repository, &["commit", "--quiet", "-m", "first"]);
+        git(&repository, &["checkout", "--quiet", "-b", "feature"]);
+        fs::write(repository.join("app/feature.txt"), "feature\n").unwrap();
+        git(&repository, &["add", "."]);
+        git(&repository, &["commit", "--quiet", "-m", "feature"]);
+        git(&repository, &["checkout", "--quiet", "-"]);
+        let url = format!("file://{}", repository.display());
+
+        let (checkout, context) = clone_git(&format!("{}#feature:app", url)).unwrap();
+        assert!(context.join("Dockerfile").is_file());
+        assert!(context.join("feature.txt").is_file());
+        assert!(!checkout.path().join(".git").exists());
+
+        // Without a fragment the default branch is checked out at the root
+        let (_checkout, context) = clone_git(&url).unwrap();
+        assert!(context.join("app/Dockerfile").is_file());
+        assert!(!context.join("app/feature.txt").exists());
+
+        assert!(clone_git(&format!("{}#feature:missing", url)).unwrap_err().contains("not found"));
+        assert!(clone_git(&format!("{}#feature:app/Dockerfile", url)).unwrap_err().contains("not a directory"));
+        assert!(clone_git(&format!("{}#no-such-branch", url)).is_err());
+    }
+
+    /// `bytes` as a stream of small chunks, so formats have to be recognized across them
+    fn chunked(bytes: &[u8]) -> Chunks {
+        let chunks: Vec<_> = bytes.chunks(100).map(|chunk| Ok(Bytes::copy_from_slice(chunk))).collect();
+        Box::pin(stream::iter(chunks))
+    }
+
+     # [tokio::test]
+    async fn streams_as_archive_or_dockerfile() {
+        let mut archive = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(0);
+        header.set_mode(0o644);
+        archive.append_data(&mut header, "build/Containerfile", [].as_slice()).unwrap();
+        let tarball = archive.into_inner().unwrap();
+
+        let context = from_stream(chunked(&tarball), Some("build/Containerfile")).await.unwrap();
+        assert_eq!(context.dockerfile, "build/Containerfile");
+        assert_eq!(collect(context.body).await, tarball);
+        assert_eq!(from_stream(chunked(&tarball), None).await.unwrap().dockerfile, "Dockerfile");
+
+        let gzip = vec![0x1f, 0x8b, 0x08, 0x00];
+        assert_eq!(collect(from_stream(chunked(&gzip), None).await.unwrap().body).await, gzip);
+        let short = from_stream(chunked(b"FROM scratch\n"), None).await.unwrap();
+        assert_eq!(entries(&collect(short.body).await), vec!["Dockerfile"]);
+
+        let dockerfile = format!("FROM scratch\n{}", "RUN echo hello\n".repeat(40));
+        let context = from_stream(chunked(dockerfile.as_bytes()), Some("ignored")).await.unwrap();
+        assert_eq!(context.dockerfile, "Dockerfile");
+        let body = collect(context.body).await;
+        assert_eq!(entries(&body), vec!["Dockerfile"]);
+        let mut contents = String::new();
+        tar::Archive::new(body.as_slice())
+            .entries()
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(contents, dockerfile);
+
+        let broken: Chunks = Box::pin(stream::iter(vec![Err(io::Error::other("cut off"))]));
+        let error = from_stream(broken, None).await.err().unwrap();
+        assert!(error.contains("cut off"), "{}", error);
+    }
+
+     # [tokio::test]
+    async fn directories_are_streamed_without_ignored_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let context = dir.path();
+        fs::create_dir_all(context.join("src")).unwrap();
+        fs::create_dir_all(context.join("target/debug")).unwrap();
+        fs::write(context.join("Dockerfile"), "FROM scratch\nCOPY . /\n").unwrap();
+        fs::write(context.join(".dockerignore"), "target\n*.log\nDockerfile\n").unwrap();
+        fs::write(context.join("src/main.rs"), vec![b'x'; 200 * 1024]).unwrap();
+        fs::write(context.join("target/debug/app"), "binary").unwrap();
+        fs::write(context.join("build.log"), "log").unwrap();
+        let path = context.to_string_lossy().to_string();
+
+        let prepared = prepare(&path, None, false, true).await.unwrap();
+        assert_eq!(prepared.dockerfile, "Dockerfile");
+        let body = collect(prepared.body).await;
+        assert_eq!(entries(&body), [".dockerignore", "Dockerfile", "src", "src/main.rs"]);
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let compressed = prepare(&path, None, true, true).await.unwrap();
+        let body = compressed.into_body(move |size| sender.send(size).unwrap());
+        let gzip = hyper::body::to_bytes(body).await.unwrap();
+        assert_eq!(receiver.try_recv().unwrap(), gzip.len() as u64);
+        let mut tarball = Vec::new();
+        flate2::read::GzDecoder::new(gzip.as_ref()).read_to_end(&mut tarball).unwrap();
+        assert_eq!(tarball, body_of(&path).await);
+
+        let error = prepare(&path, Some("missing"), false, true).await.err().unwrap();
+        assert!(error.contains("cannot locate specified Dockerfile"), "{}", error);
+    }
+
+     # [tokio::test]
+    async fn downloads_remote_context() {
+        let url = serve_once("200 OK", b"FROM scratch\n");
+        assert_eq!(collect(download(&url, true).await.unwrap()).await, b"FROM scratch\n");
+
+        let url = serve_once("404 Not Found", b"");
+        let error = download(&url, true).await.err().unwrap();
+        assert!(error.contains("404"), "{}", error);
+    }
+
+     # [test]
+    fn globs() {
+        let matches = |pattern: &str, path: &str| glob_match(pattern.as_bytes(), path.as_bytes());
+        assert!(matches("*.md", "README.md"));
+        assert!(!matches("*.md", "docs/README.md"));
+        assert!(matches("docs/*", "docs/a.md"));
+        assert!(matches("?.txt", "a.txt"));
+        assert!(!matches("?.txt", "ab.txt"));
+        assert!(!matches("a?b", "a/b"));
+
+        assert!(matches("**/*.md", "README.md"));
+        assert!(matches("**/*.md", "docs/api/README.md"));
+        assert!(matches("docs/**", "docs/api/README.md"));
+        assert!(matches("docs/**/index.html", "docs/index.html"));
+        assert!(matches("docs/**/index.html", "docs/a/b/index.html"));
+        assert!(!matches("docs/**/index.html", "site/docs/index.html"));
+
+        assert!(matches("file[0-9].txt", "file7.txt"));
+        assert!(!matches("file[0-9].txt", "filex.txt"));
+        assert!(matches("file[^0-9].txt", "filex.txt"));
+        assert!(matches("file[!0-9].txt", "filex.txt"));
+        assert!(!matches("file[!0-9].txt", "file7.txt"));
+        assert!(matches("[]a]", "]"));
+        assert!(matches("[a\\-z]", "-"));
+        assert!(!matches("[a\\-z]", "m"));
+        assert!(!matches("[ab", "a"));
+        assert!(!matches("a[/]b", "a/b"));
+
+        assert!(matches("\\*.txt", "*.txt"));
+        assert!(!matches("\\*.txt", "a.txt"));
+    }
+
+     # [test]
+    fn ignore_rules() {
+        let lines: Vec<String> = [
+            "# comment",
+            "",
+            "**/*.log",
+            "node_modules",
+            "/build/../tmp",
+            "docs/*",
+            "!docs/README.md",
+            "secret[0-9]",
+            "!important.log",
+        ]
+        .iter()
+        .map(|line| line.to_string())
+        .collect();
+        let matcher = IgnoreMatcher::new(&lines);
+
+        assert!(matcher.excluded("debug.log"));
+        assert!(matcher.excluded("logs/deep/debug.log"));
+        assert!(!matcher.excluded("important.log"));
+        // Excluding a directory excludes everything below it
+        assert!(matcher.excluded("node_modules"));
+        assert!(matcher.excluded("node_modules/pkg/index.js"));
+        assert!(!matcher.excluded("src/node_modules.rs"));
+        assert!(matcher.excluded("tmp/cache"));
+        assert!(!matcher.excluded("build"));
+        // The last matching rule wins
+        assert!(matcher.excluded("docs/guide.md"));
+        assert!(!matcher.excluded("docs/README.md"));
+        assert!(matcher.excluded("secret1"));
+        assert!(!matcher.excluded("secretx"));
+        assert!(!matcher.excluded("# comment"));
+        assert!(matcher.has_exclusions());
+    }
+
+     # [test]
+    fn kept_paths_override_rules() {
+        let mut matcher = IgnoreMatcher::new(&["*".to_string()]);
+        assert!(matcher.excluded("Dockerfile"));
+        assert!(!matcher.has_exclusions());
+        matcher.keep("./Dockerfile");
+        matcher.keep("weird[1].Dockerfile");
+        assert!(!matcher.excluded("Dockerfile"));
+        assert!(!matcher.excluded("weird[1].Dockerfile"));
+        assert!(matcher.excluded("weird1.Dockerfile"));
+        assert!(matcher.excluded("main.rs"));
+    }
+}
@@ -0,0 +1,935 @@
+//! Dockerfile parsing and linting.
+//!
+//! The parser follows the BuildKit frontend: parser directives, line
+//! continuations with the escape character, heredocs and ARG/ENV substitution
+//! in the instructions that support it.
+
+use clap::Subcommand;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{
+        self,
+        Read
+    },
+    process,
+};
+
+/// Every instruction the Dockerfile frontend knows
+const INSTRUCTIONS: [&str; 18] = [
+    "ADD", "ARG", "CMD", "COPY", "ENTRYPOINT", "ENV", "EXPOSE", "FROM", "HEALTHCHECK", "LABEL",
+    "MAINTAINER", "ONBUILD", "RUN", "SHELL", "STOPSIGNAL", "USER", "VOLUME", "WORKDIR",
+];
+
+/// Instructions whose arguments go through variable substitution
+const EXPANDED: [&str; 11] = ["ADD", "ARG", "COPY", "ENV", "EXPOSE", "FROM", "LABEL", "STOPSIGNAL", "USER", "VOLUME", "WORKDIR"];
+
+/// Instructions whose arguments may be written as a JSON array
+const JSON_FORM: [&str; 7] = ["ADD", "CMD", "COPY", "ENTRYPOINT", "RUN", "SHELL", "VOLUME"];
+
+/// Instructions that take `--name=value` flags before their arguments
+const WITH_FLAGS: [&str; 5] = ["ADD", "COPY", "FROM", "HEALTHCHECK", "RUN"];
+
+/// Instructions that can carry heredocs
+const WITH_HEREDOCS: [&str; 3] = ["ADD", "COPY", "RUN"];
+
+/// Parser directives recognised at the top of the file
+const DIRECTIVES: [&str; 3] = ["check", "escape", "syntax"];
+
+ # [derive(Subcommand, Debug)]
+pub enum DockerfileCommand {
+    /// Check a Dockerfile against common mistakes
+    Lint {
+        /// Path to the Dockerfile, or - for stdin
+         # [arg(default_value = "Dockerfile")]
+        file: String,
+    },
+
+    /// Print the parsed instructions of a Dockerfile
+    Print {
+        /// Path to the Dockerfile, or - for stdin
+         # [arg(default_value = "Dockerfile")]
+        file: String,
+
+        /// Print the parse tree as JSON
+         # [arg(long)]
+        json: bool,
+    },
+}
+
+pub async fn handle_dockerfile_command(command: &DockerfileCommand) {
+    match command {
+        DockerfileCommand::Lint {
+            file
+        }
+         => {
+            lint_file(file);
+        }
+        DockerfileCommand::Print {
+            file,
+            json
+        }
+         => {
+            print_file(file, *json);
+        }
+    }
+}
+
+ # [derive(Serialize, Debug)]
+pub struct Dockerfile {
+    pub directives: BTreeMap<String, String>,
+    pub stages: Vec<Stage>,
+    pub instructions: Vec<Instruction>,
+}
+
+ # [derive(Serialize, Debug)]
+pub struct Stage {
+    pub name: Option<String>,
+    /// Base image after substitution
+    pub base: String,
+    pub line: usize,
+}
+
+ # [derive(Serialize, Debug)]
+ # [serde(rename_all = "camelCase")]
+pub struct Instruction {
+    /// Upper case instruction name
+    pub keyword: String,
+    pub flags: Vec<String>,
+    /// Arguments after substitution: the exec form elements, `key=value` pairs for
+    /// ENV and LABEL, the command for shell forms, or the words otherwise
+    pub args: Vec<String>,
+    /// Whether the arguments were written as a JSON array
+    pub json: bool,
+    pub heredocs: Vec<Heredoc>,
+    /// Source text with continuations joined
+    pub original: String,
+    pub line: usize,
+    pub end_line: usize,
+}
+
+ # [derive(Serialize, Debug)]
+pub struct Heredoc {
+    pub name: String,
+    pub content: String,
+    /// `<<-` strips leading tabs
+    pub chomp: bool,
+    /// An unquoted name means the content is expanded when the build runs
+    pub expand: bool,
+}
+
+/// An instruction as written, before substitution
+struct RawInstruction {
+    keyword: String,
+    rest: String,
+    heredocs: Vec<Heredoc>,
+    original: String,
+    line: usize,
+    end_line: usize,
+}
+
+struct Lines<'a> {
+    lines: Vec<&'a str>,
+    next: usize,
+    escape: char,
+}
+
+impl<'a> Lines<'a> {
+    /// Joins continuation lines into the next instruction and collects its heredocs
+    fn next_instruction(&mut self) -> Result<Option<RawInstruction>, String> {
+        while self.next < self.lines.len() && is_blank_or_comment(self.lines[self.next]) {
+            self.next += 1;
+        }
+        if self.next >= self.lines.len() {
+            return Ok(None);
+        }
+        let line = self.next + 1;
+        let mut original = String::new();
+        let mut first = true;
+        while self.next < self.lines.len() {
+            let text = self.lines[self.next];
+            self.next += 1;
+            // Comments and empty lines inside a continuation are dropped
+            if !first && is_blank_or_comment(text) {
+                continue;
+            }
+            first = false;
+            let trimmed = text.trim_end();
+            match trimmed.strip_suffix(self.escape) {
+                Some(continued) => original.push_str(continued),
+                None => {
+                    original.push_str(text);
+                    break;
+                }
+            }
+        }
+        let mut end_line = self.next;
+
+        let trimmed = original.trim();
+        let (word, rest) = trimmed.split_once(char::is_whitespace).unwrap_or((trimmed, ""));
+        let keyword = word.to_uppercase();
+        if !INSTRUCTIONS.contains(&keyword.as_str()) {
+            return Err(format!("line {}: unknown instruction: {}", line, word));
+        }
+
+        let mut heredocs = Vec::new();
+        if WITH_HEREDOCS.contains(&keyword.as_str()) {
+            for (name, chomp, expand) in heredoc_markers(rest) {
+                let mut content = String::new();
+                loop {
+                    let text = match self.lines.get(self.next) {
+                        Some(text) => *text,
+                        None => return Err(format!("line {}: unterminated heredoc {}", line, name)),
+                    };
+                    self.next += 1;
+                    let text = if chomp { text.trim_start_matches('\t') } else { text };
+                    if text == name {
+                        break;
+                    }
+                    content.push_str(text);
+                    content.push('\n');
+                }
+                end_line = self.next;
+                heredocs.push(Heredoc { name, content, chomp, expand });
+            }
+        }
+
+        Ok(Some(RawInstruction {
+            keyword,
+            rest: rest.trim().to_string(),
+            heredocs,
+            original: trimmed.to_string(),
+            line,
+            end_line,
+        }))
+    }
+}
+
+fn is_blank_or_comment(line: &str) -> bool {
+    let line = line.trim_start();
+    line.is_empty() || line.starts_with('#')
+}
+
+/// Splits a shell command into words as written, keeping their quotes
+fn shell_words(text: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut start = None;
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match (quote, c) {
+            (Some('\''), '\'') => quote = None,
+            (Some('\''), _) => {}
+            (_, '\\') => escaped = true,
+            (Some('"'), '"') => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            (None, c) if c.is_whitespace() => {
+                if let Some(begin) = start.take() {
+                    words.push(&text[begin..i]);
+                }
+                continue;
+            }
+            _ => {}
+        }
+        start.get_or_insert(i);
+    }
+    if let Some(begin) = start {
+        words.push(&text[begin..]);
+    }
+    words
+}
+
+/// Finds `<<NAME`, `<<-NAME` and `<<"NAME"` markers, in order.
+///
+/// Like BuildKit, only an unquoted word starting with `<<` (or `2<<` and the
+/// like) opens a heredoc, so `"a<<b"` and `$((1<<4))` are left alone.
+fn heredoc_markers(text: &str) -> Vec<(String, bool, bool)> {
+    let mut markers = Vec::new();
+    for word in shell_words(text) {
+        let Some(marker) = word.trim_start_matches(|c: char| c.is_ascii_digit()).strip_prefix("<<") else {
+            continue;
+        };
+        let chomp = marker.starts_with('-');
+        let marker = marker.strip_prefix('-').unwrap_or(marker);
+        let name_end = |name: &str| name.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(name.len());
+        match marker.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let name = &marker[1..];
+                let end = name_end(name);
+                if end > 0 && name[end..].starts_with(quote) {
+                    markers.push((name[..end].to_string(), chomp, false));
+                }
+            }
+            _ => {
+                let end = name_end(marker);
+                if end > 0 {
+                    markers.push((marker[..end].to_string(), chomp, true));
+                }
+            }
+        }
+    }
+    markers
+}
+
+/// Reads `# key=value` parser directives and returns the escape character
+fn read_directives(lines: &[&str]) -> Result<(BTreeMap<String, String>, char, usize), String> {
+    let mut directives = BTreeMap::new();
+    let mut consumed = 0;
+    for line in lines {
+        let directive = line
+            .trim()
+            .strip_prefix('#')
+            .and_then(|comment| comment.split_once('='))
+            .map(|(key, value)| (key.trim().to_lowercase(), value.trim().to_string()))
+            .filter(|(key, _)| DIRECTIVES.contains(&key.as_str()) && !directives.contains_key(key));
+        match directive {
+            Some((key, value)) => {
+                directives.insert(key, value);
+                consumed += 1;
+            }
+            None => break,
+        }
+    }
+    let escape = match directives.get("escape").map(String::as_str) {
+        None | Some("\\") => '\\',
+        Some("`") => '`',
+        Some(other) => return Err(format!("invalid escape token '{}' does not match ` or \\", other)),
+    };
+    Ok((directives, escape, consumed))
+}
+
+/// Parses a whole Dockerfile
+pub fn parse(text: &str) -> Result<Dockerfile, String> {
+    let all: Vec<&str> = text.lines().collect();
+    let (directives, escape, consumed) = read_directives(&all)?;
+    let mut lines = Lines { lines: all, next: consumed, escape };
+
+    let mut stages: Vec<Stage> = Vec::new();
+    let mut instructions = Vec::new();
+    // ARGs before the first FROM can only be used in FROM lines
+    let mut global_args: BTreeMap<String, String> = BTreeMap::new();
+    let mut stage_env: Vec<BTreeMap<String, String>> = Vec::new();
+    let mut env: BTreeMap<String, String> = BTreeMap::new();
+    let mut args: BTreeMap<String, String> = BTreeMap::new();
+
+    while let Some(raw) = lines.next_instruction()? {
+        if stages.is_empty() && raw.keyword != "FROM" && raw.keyword != "ARG" {
+            return Err(format!("line {}: {} must come after FROM, only ARG may precede it", raw.line, raw.keyword));
+        }
+        let instruction = {
+            let variables = if stages.is_empty() || raw.keyword == "FROM" {
+                global_args.clone()
+            }
+            else {
+                let mut variables = args.clone();
+                variables.extend(env.clone());
+                variables
+            };
+            build_instruction(raw, escape, &variables)?
+        };
+
+        match instruction.keyword.as_str() {
+            "FROM" => {
+                let (base, name) = match instruction.args.as_slice() {
+                    [base] => (base.clone(), None),
+                    [base, as_word, name] if as_word.eq_ignore_ascii_case("as") => (base.clone(), Some(name.to_lowercase())),
+                    _ => return Err(format!("line {}: FROM requires either one or three arguments", instruction.line)),
+                };
+                if stages.len() > stage_env.len() {
+                    stage_env.push(env.clone());
+                }
+                // A stage built on an earlier one starts with its environment
+                env = stages
+                    .iter()
+                    .position(|stage| stage.name.as_deref() == Some(base.to_lowercase().as_str()))
+                    .map(|index| stage_env[index].clone())
+                    .unwrap_or_default();
+                args.clear();
+                stages.push(Stage { name, base, line: instruction.line });
+            }
+            "ARG" => {
+                for arg in &instruction.args {
+                    let (name, value) = match arg.split_once('=') {
+                        Some((name, value)) => (name.to_string(), Some(value.to_string())),
+                        None => (arg.clone(), None),
+                    };
+                    if stages.is_empty() {
+                        global_args.insert(name, value.unwrap_or_default());
+                    }
+                    else {
+                        let value = value.or_else(|| global_args.get(&name).cloned()).unwrap_or_default();
+                        args.insert(name, value);
+                    }
+                }
+            }
+            "ENV" => {
+                for pair in &instruction.args {
+                    if let Some((name, value)) = pair.split_once('=') {
+                        env.insert(name.to_string(), value.to_string());
+                    }
+                }
+            }
+            _ => {}
+        }
+        instructions.push(instruction);
+    }
+    if stages.is_empty() {
+        return Err("the Dockerfile has no FROM instruction".to_string());
+    }
+    Ok(Dockerfile { directives, stages, instructions })
+}
+
+/// Parses a single instruction, as given to `--change`
+pub fn parse_instruction(text: &str) -> Result<Instruction, String> {
+    let mut lines = Lines { lines: text.lines().collect(), next: 0, escape: '\\' };
+    let raw = lines.next_instruction()?.ok_or_else(|| "empty instruction".to_string())?;
+    if lines.next_instruction()?.is_some() {
+        return Err(format!("expected a single instruction: {}", text));
+    }
+    build_instruction(raw, '\\', &BTreeMap::new())
+}
+
+fn build_instruction(raw: RawInstruction, escape: char, variables: &BTreeMap<String, String>) -> Result<Instruction, String> {
+    let keyword = raw.keyword.as_str();
+    let expands = EXPANDED.contains(&keyword);
+    let expand_text = |text: &str| if expands { expand(text, variables, escape) } else { text.to_string() };
+
+    let mut rest = raw.rest.as_str();
+    let mut flags = Vec::new();
+    if WITH_FLAGS.contains(&keyword) {
+        while rest.starts_with("--") {
+            let (flag, remaining) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            // Flag values are always expanded, e.g. COPY --from=${STAGE}
+            flags.push(expand(flag, variables, escape));
+            rest = remaining.trim_start();
+        }
+    }
+
+    let exec_form = if JSON_FORM.contains(&keyword) && rest.starts_with('[') {
+        serde_json::from_str::<Vec<String>>(rest).ok()
+    }
+    else {
+        None
+    };
+    let json = exec_form.is_some();
+    let args = match exec_form {
+        Some(elements) => elements.iter().map(|element| expand_text(element)).collect(),
+        None => match keyword {
+            "ENV" | "LABEL" => key_values(&expand_text(rest), escape)
+                .map_err(|e| format!("line {}: {} {}", raw.line, keyword, e))?,
+            "RUN" | "CMD" | "ENTRYPOINT" | "SHELL" | "HEALTHCHECK" | "MAINTAINER" | "ONBUILD" => {
+                if rest.is_empty() {
+                    Vec::new()
+                }
+                else {
+                    vec![rest.to_string()]
+                }
+            }
+            _ => split_words(&expand_text(rest), escape),
+        },
+    };
+    if args.is_empty() && raw.heredocs.is_empty() && keyword != "CMD" && keyword != "ENTRYPOINT" {
+        return Err(format!("line {}: {} requires at least one argument", raw.line, keyword));
+    }
+
+    Ok(Instruction {
+        keyword: raw.keyword,
+        flags,
+        args,
+        json,
+        heredocs: raw.heredocs,
+        original: raw.original,
+        line: raw.line,
+        end_line: raw.end_line,
+    })
+}
+
+/// Substitutes `$VAR`, `${VAR}`, `${VAR:-default}` and `${VAR:+alternative}`.
+///
+/// Single quoted text is left alone and the escape character keeps a `$` literal.
+pub fn expand(text: &str, variables: &BTreeMap<String, String>, escape: char) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut expanded = String::new();
+    let mut in_single = false;
+    let mut in_double = false;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == escape && !in_single && i + 1 < chars.len() {
+            // Keep the escape so word splitting still sees it, unless it protects a `$`
+            if chars[i + 1] != '$' {
+                expanded.push(c);
+            }
+            expanded.push(chars[i + 1]);
+            i += 2;
+            continue;
+        }
+        match c {
+            '\'' if !in_double => in_single = !in_single,
+            '"' if !in_single => in_double = !in_double,
+            '$' if !in_single => {
+                if let Some((value, consumed)) = variable_at(&chars[i + 1..], variables) {
+                    expanded.push_str(&value);
+                    i += 1 + consumed;
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        expanded.push(c);
+        i += 1;
+    }
+    expanded
+}
+
+/// Reads the variable reference after a `$`, returning its value and length
+fn variable_at(chars: &[char], variables: &BTreeMap<String, String>) -> Option<(String, usize)> {
+    let is_name = |c: &char| c.is_ascii_alphanumeric() || *c == '_';
+    if chars.first() == Some(&'{') {
+        let close = chars.iter().position(|c| *c == '}')?;
+        let inner: String = chars[1..close].iter().collect();
+        let value = match inner.split_once(":-") {
+            Some((name, default)) => variables.get(name).filter(|v| !v.is_empty()).cloned().unwrap_or_else(|| expand(default, variables, '\\')),
+            None => match inner.split_once(":+") {
+                Some((name, alternative)) => match variables.get(name).filter(|v| !v.is_empty()) {
+                    Some(_) => expand(alternative, variables, '\\'),
+                    None => String::new(),
+                },
+                None => variables.get(&inner).cloned().unwrap_or_default(),
+            },
+        };
+        return Some((value, close + 1));
+    }
+    let length = chars.iter().take_while(|c| is_name(c)).count();
+    if length == 0 {
+        return None;
+    }
+    let name: String = chars[..length].iter().collect();
+    Some((variables.get(&name).cloned().unwrap_or_default(), length))
+}
+
+/// Splits on unquoted whitespace, removing quotes and escapes
+fn split_words(text: &str, escape: char) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quote = None;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some('"') if c == escape => {
+                if let Some(next) = chars.next() {
+                    word.push(next);
+                }
+            }
+            Some(_) => word.push(c),
+            None if c == '"' || c == '\'' => {
+                quote = Some(c);
+                in_word = true;
+            }
+            None if c == escape => {
+                if let Some(next) = chars.next() {
+                    word.push(next);
+                }
+                in_word = true;
+            }
+            None if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            None => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    words
+}
+
+/// Parses `key=value ...`, or the legacy `key value` form of ENV and LABEL
+fn key_values(text: &str, escape: char) -> Result<Vec<String>, String> {
+    let words = split_words(text, escape);
+    match words.first() {
+        None => Ok(Vec::new()),
+        Some(first) if !first.contains('=') => {
+            let value = text.trim()[text.trim().find(char::is_whitespace).unwrap_or(text.trim().len())..].trim();
+            if value.is_empty() {
+                return Err(format!("{} must have a value", first));
+            }
+            Ok(vec![format!("{}={}", first, value)])
+        }
+        Some(_) => {
+            for word in &words {
+                if !word.contains('=') || word.starts_with('=') {
+                    return Err(format!("syntax error - can't find = in {:?}, must be of the form: name=value", word));
+                }
+            }
+            Ok(words)
+        }
+    }
+}
+
+/// A problem found by `lint`
+pub struct Finding {
+    pub line: usize,
+    pub rule: &'static str,
+    pub message: String,
+}
+
+/// Runs the lint rules over a parsed Dockerfile
+pub fn lint(dockerfile: &Dockerfile) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let stage_names: Vec<&str> = dockerfile.stages.iter().filter_map(|stage| stage.name.as_deref()).collect();
+
+    for stage in &dockerfile.stages {
+        let base = stage.base.as_str();
+        if base.eq_ignore_ascii_case("scratch") || stage_names.contains(&base.to_lowercase().as_str()) || base.contains('$') || base.contains('@') {
+            continue;
+        }
+        let name = base.rsplit('/').next().unwrap_or(base);
+        match name.split_once(':') {
+            None => findings.push(Finding {
+                line: stage.line,
+                rule: "unpinned-base-image",
+                message: format!("base image {:?} has no tag; pin a version or digest", base),
+            }),
+            Some((_, "latest")) => findings.push(Finding {
+                line: stage.line,
+                rule: "unpinned-base-image",
+                message: format!("base image {:?} uses the latest tag; pin a version or digest", base),
+            }),
+            Some(_) => {}
+        }
+    }
+
+    for instruction in &dockerfile.instructions {
+        match instruction.keyword.as_str() {
+            "RUN" => {
+                let mut script = instruction.args.join(" ");
+                for heredoc in &instruction.heredocs {
+                    script.push('\n');
+                    script.push_str(&heredoc.content);
+                }
+                if installs_packages(&script) && !script.contains("/var/lib/apt/lists") {
+                    findings.push(Finding {
+                        line: instruction.line,
+                        rule: "apt-get-no-cleanup",
+                        message: "apt-get install without removing /var/lib/apt/lists/* in the same RUN".to_string(),
+                    });
+                }
+            }
+            "ADD" => {
+                let sources = &instruction.args[..instruction.args.len().saturating_sub(1)];
+                for source in sources {
+                    if source.starts_with("http://") || source.starts_with("https://") {
+                        findings.push(Finding {
+                            line: instruction.line,
+                            rule: "add-url",
+                            message: format!("ADD downloads {}; fetch it with curl or wget in a RUN instead", source),
+                        });
+                    }
+                }
+            }
+            "CMD" if !instruction.json && !instruction.args.is_empty() => findings.push(Finding {
+                line: instruction.line,
+                rule: "shell-form-cmd",
+                message: "CMD uses the shell form; use the JSON form so the process receives signals".to_string(),
+            }),
+            _ => {}
+        }
+    }
+
+    // Only the final stage decides which user the image runs as
+    if let Some(last) = dockerfile.stages.last() {
+        let user = dockerfile
+            .instructions
+            .iter()
+            .rev()
+            .find(|instruction| instruction.line > last.line && instruction.keyword == "USER")
+            .and_then(|instruction| instruction.args.first());
+        let is_root = |user: &str| {
+            let name = user.split(':').next().unwrap_or(user);
+            name == "root" || name == "0"
+        };
+        match user {
+            None => findings.push(Finding {
+                line: last.line,
+                rule: "missing-user",
+                message: "the final stage has no USER instruction and runs as root".to_string(),
+            }),
+            Some(user) if is_root(user) => findings.push(Finding {
+                line: last.line,
+                rule: "missing-user",
+                message: format!("the final stage runs as {}", user),
+            }),
+            Some(_) => {}
+        }
+    }
+
+    findings.sort_by_key(|finding| finding.line);
+    findings
+}
+
+/// Whether any command of a shell script is an `apt-get ... install`
+fn installs_packages(script: &str) -> bool {
+    script
+        .split(['&', ';', '|', '\n'])
+        .map(|command| command.split_whitespace().collect::<Vec<_>>())
+        .any(|words| words.iter().position(|w| *w == "apt-get").is_some_and(|i| words[i..].contains(&"install")))
+}
+
+fn read_file(file: &str) -> String {
+    let read = if file == "-" {
+        let mut text = String::new();
+        io::stdin().read_to_string(&mut text).map(|_| text)
+    }
+    else {
+        fs::read_to_string(file)
+    };
+    match read {
+        Ok(text) => text,
+        Err(e) => {
+            eprintln!("Error: failed to read {}: {}", file, e);
+            process::exit(1);
+        }
+    }
+}
+
+fn parse_file(file: &str) -> Dockerfile {
+    match parse(&read_file(file)) {
+        Ok(dockerfile) => dockerfile,
+        Err(e) => {
+            eprintln!("Error: {}: {}", file, e);
+            process::exit(1);
+        }
+    }
+}
+
+pub fn lint_file(file: &str) {
+    let dockerfile = parse_file(file);
+    let findings = lint(&dockerfile);
+    for finding in &findings {
+        println!("{}:{} {}: {}", file, finding.line, finding.rule, finding.message);
+    }
+    if !findings.is_empty() {
+        process::exit(1);
+    }
+}
+
+pub fn print_file(file: &str, json: bool) {
+    let dockerfile = parse_file(file);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&dockerfile).unwrap());
+        return;
+    }
+    for (key, value) in &dockerfile.directives {
+        println!("# {}={}", key, value);
+    }
+    for instruction in &dockerfile.instructions {
+        let mut line = instruction.keyword.clone();
+        for flag in &instruction.flags {
+            line.push(' ');
+            line.push_str(flag);
+        }
+        let args = if instruction.json {
+            serde_json::to_string(&instruction.args).unwrap()
+        }
+        else if instruction.keyword == "ENV" || instruction.keyword == "LABEL" {
+            instruction
+                .args
+                .iter()
+                .map(|pair| match pair.split_once('=') {
+                    Some((key, value)) if value.is_empty() || value.contains(char::is_whitespace) => format!("{}={:?}", key, value),
+                    _ => pair.clone(),
+                })
+                .collect::<Vec<_>>()
+                .join(" ")
+        }
+        else {
+            instruction.args.join(" ")
+        };
+        if !args.is_empty() {
+            line.push(' ');
+            line.push_str(&args);
+        }
+        println!("{}", line);
+        for heredoc in &instruction.heredocs {
+            print!("{}", heredoc.content);
+            println!("{}", heredoc.name);
+        }
+    }
+}
+
+ # [cfg(test)]
+mod tests {
+    use super::{
+        expand,
+        key_values,
+        lint,
+        parse
+    };
+    use std::collections::BTreeMap;
+
+    /// Rules reported for a Dockerfile, with their lines
+    fn findings(text: &str) -> Vec<(&'static str, usize)> {
+        lint(&parse(text).unwrap()).iter().map(|finding| (finding.rule, finding.line)).collect()
+    }
+
+    fn variables(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    fn run_heredocs(run: &str) -> Result<Vec<String>, String> {
+        let dockerfile = parse(&format!("FROM alpine\n{}\n", run))?;
+        Ok(dockerfile.instructions[1].heredocs.iter().map(|heredoc| heredoc.name.clone()).collect())
+    }
+
+     # [test]
+    fn heredocs_open_on_unquoted_words() {
+        let dockerfile = parse("FROM alpine\nRUN <<EOF\necho hi\nEOF\nRUN cat <<-\"END\" >/x\n\tliteral $HOME\n\tEND\n").unwrap();
+        let first = &dockerfile.instructions[1].heredocs[0];
+        assert_eq!((first.name.as_str(), first.content.as_str(), first.expand), ("EOF", "echo hi\n", true));
+        let second = &dockerfile.instructions[2].heredocs[0];
+        assert_eq!((second.name.as_str(), second.chomp, second.expand), ("END", true, false));
+        assert_eq!(dockerfile.instructions[2].end_line, 7);
+        assert_eq!(run_heredocs("COPY <<one 2<<two /dst/\na\none\nb\ntwo").unwrap(), ["one", "two"]);
+    }
+
+     # [test]
+    fn shifts_and_quoted_text_are_not_heredocs() {
+        assert_eq!(run_heredocs("RUN echo \"a<<b\"").unwrap(), Vec::<String>::new());
+        assert_eq!(run_heredocs("RUN echo 'x <<y'").unwrap(), Vec::<String>::new());
+        assert_eq!(run_heredocs("RUN echo $((1<<4))").unwrap(), Vec::<String>::new());
+        assert_eq!(run_heredocs("RUN echo $(( 1 << 4 ))").unwrap(), Vec::<String>::new());
+        assert_eq!(run_heredocs("RUN echo a\\ <<b").unwrap(), Vec::<String>::new());
+        assert!(run_heredocs("RUN cat <<EOF").unwrap_err().contains("unterminated heredoc EOF"));
+    }
+
+     # [test]
+    fn continuations_are_joined() {
+        let dockerfile = parse("FROM alpine\nRUN apk add \\\n    # a comment inside\n\n    curl \\\n    git\nUSER app\n").unwrap();
+        let run = &dockerfile.instructions[1];
+        assert_eq!(run.args, ["apk add     curl     git"]);
+        assert_eq!((run.line, run.end_line), (2, 6));
+        assert_eq!(dockerfile.instructions[2].line, 7);
+    }
+
+     # [test]
+    fn escape_directive_changes_the_continuation_character() {
+        let dockerfile = parse("# escape=`\nFROM mcr.microsoft.com/windows\nRUN dir C:\\ `\n    /b\n").unwrap();
+        assert_eq!(dockerfile.directives["escape"], "`");
+        assert_eq!(dockerfile.instructions[1].args, ["dir C:\\     /b"]);
+        assert!(parse("# escape=x\nFROM alpine\n").is_err());
+    }
+
+     # [test]
+    fn unpinned_base_images() {
+        assert_eq!(findings("FROM alpine\nUSER app\n"), [("unpinned-base-image", 1)]);
+        assert_eq!(findings("FROM alpine:latest\nUSER app\n"), [("unpinned-base-image", 1)]);
+        assert_eq!(findings("FROM localhost:5000/team/app\nUSER app\n"), [("unpinned-base-image", 1)]);
+        assert_eq!(findings("FROM alpine:3.20\nUSER app\n"), []);
+        assert_eq!(findings("FROM localhost:5000/team/app:1.2\nUSER app\n"), []);
+        assert_eq!(findings("FROM alpine@sha256:0123456789abcdef\nUSER app\n"), []);
+        assert_eq!(findings("FROM scratch\nUSER app\n"), []);
+        assert_eq!(findings("ARG BASE=alpine\nFROM ${BASE}:3.20\nUSER app\n"), []);
+        // A stage built on an earlier stage is pinned by that stage
+        assert_eq!(findings("FROM golang:1.23 AS Build\nFROM build\nUSER app\n"), []);
+    }
+
+     # [test]
+    fn apt_get_without_cleanup() {
+        let base = "FROM debian:12\nUSER app\n";
+        assert_eq!(findings(&format!("{}RUN apt-get update && apt-get install -y curl\n", base)), [("apt-get-no-cleanup", 3)]);
+        assert_eq!(findings(&format!("{}RUN <<EOF\napt-get update\napt-get -y install curl\nEOF\n", base)), [("apt-get-no-cleanup", 3)]);
+        assert_eq!(
+            findings(&format!("{}RUN apt-get update && apt-get install -y curl && rm -rf /var/lib/apt/lists/*\n", base)),
+            []
+        );
+        assert_eq!(findings(&format!("{}RUN apt-get update\nRUN echo install\n", base)), []);
+    }
+
+     # [test]
+    fn add_from_urls() {
+        let base = "FROM alpine:3.20\nUSER app\n";
+        assert_eq!(findings(&format!("{}ADD https://example.com/a.tgz /opt/\n", base)), [("add-url", 3)]);
+        assert_eq!(findings(&format!("{}ADD --chown=app http://example.com/a local /opt/\n", base)), [("add-url", 3)]);
+        assert_eq!(findings(&format!("{}ADD app.tgz /opt/\n", base)), []);
+        assert_eq!(findings(&format!("{}COPY https://example.com/a /opt/\n", base)), []);
+    }
+
+     # [test]
+    fn shell_form_cmd() {
+        let base = "FROM alpine:3.20\nUSER app\n";
+        assert_eq!(findings(&format!("{}CMD nginx -g 'daemon off;'\n", base)), [("shell-form-cmd", 3)]);
+        assert_eq!(findings(&format!("{}CMD [\"nginx\", \"-g\", \"daemon off;\"]\n", base)), []);
+        assert_eq!(findings(&format!("{}CMD\n", base)), []);
+    }
+
+     # [test]
+    fn missing_user() {
+        assert_eq!(findings("FROM alpine:3.20\nRUN true\n"), [("missing-user", 1)]);
+        assert_eq!(findings("FROM alpine:3.20\nUSER root\n"), [("missing-user", 1)]);
+        assert_eq!(findings("FROM alpine:3.20\nUSER app\nUSER 0:0\n"), [("missing-user", 1)]);
+        // Only the final stage counts
+        assert_eq!(findings("FROM alpine:3.20 AS build\nUSER app\nFROM alpine:3.20\n"), [("missing-user", 3)]);
+        assert_eq!(findings("FROM alpine:3.20 AS build\nFROM alpine:3.20\nUSER root\nUSER 1000:1000\n"), []);
+    }
+
+     # [test]
+    fn variable_substitution() {
+        let vars = variables(&[("NAME", "app"), ("EMPTY", "")]);
+        assert_eq!(expand("$NAME-${NAME}_x", &vars, '\\'), "app-app_x");
+        assert_eq!(expand("${MISSING}|$MISSING|${EMPTY}", &vars, '\\'), "||");
+        assert_eq!(expand("${MISSING:-def}|${EMPTY:-def}|${NAME:-def}", &vars, '\\'), "def|def|app");
+        assert_eq!(expand("${MISSING:-$NAME}", &vars, '\\'), "app");
+        assert_eq!(expand("${NAME:+alt}|${EMPTY:+alt}|${MISSING:+alt}", &vars, '\\'), "alt||");
+        assert_eq!(expand("'$NAME' \"$NAME\"", &vars, '\\'), "'$NAME' \"app\"");
+        assert_eq!(expand("\\$NAME \\n", &vars, '\\'), "$NAME \\n");
+        assert_eq!(expand("`$NAME", &vars, '`'), "$NAME");
+    }
+
+     # [test]
+    fn env_and_label_pairs() {
+        assert_eq!(key_values("A=1 B=\"two words\" C=a\\ b", '\\').unwrap(), ["A=1", "B=two words", "C=a b"]);
+        assert_eq!(key_values("LEGACY the rest  of it", '\\').unwrap(), ["LEGACY=the rest  of it"]);
+        assert!(key_values("LEGACY", '\\').unwrap_err().contains("must have a value"));
+        assert!(key_values("A=1 B", '\\').unwrap_err().contains("can't find ="));
+        assert!(key_values("=1", '\\').is_err());
+    }
+
+     # [test]
+    fn args_and_env_are_scoped_to_stages() {
+        let dockerfile = parse(concat!(
+            "ARG VERSION=3.20\n",
+            "FROM alpine:${VERSION} AS base\n",
+            "ENV APP=/srv/app\n",
+            "WORKDIR ${APP}/${VERSION}\n",
+            "ARG VERSION\n",
+            "WORKDIR ${VERSION}\n",
+            "FROM base AS child\n",
+            "COPY . ${APP}\n",
+            "FROM alpine:3.20\n",
+            "COPY . ${APP:-/default}\n",
+        ))
+        .unwrap();
+        let args = |index: usize| dockerfile.instructions[index].args.clone();
+        assert_eq!(dockerfile.stages[0].base, "alpine:3.20");
+        // Global ARGs only reach FROM lines until a stage declares them again
+        assert_eq!(args(3), ["/srv/app/"]);
+        assert_eq!(args(5), ["3.20"]);
+        assert_eq!(args(7), [".", "/srv/app"]);
+        assert_eq!(args(9), [".", "/default"]);
+    }
+}
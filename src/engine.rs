@@ -0,0 +1,275 @@
+//! Direct access to the Engine API endpoints that shiplift does not wrap.
+//!
+//! shiplift keeps its `Docker` transport private, so this module builds its own
+//! `Transport` from `DOCKER_HOST` the same way `Docker::new()` does and exposes
+//! a few small request helpers on top of it.
+
+use futures::{
+    stream,
+    Stream,
+    StreamExt,
+};
+use hyper::{
+    body::Bytes,
+    client::{
+        Client,
+        HttpConnector
+    },
+    header::{
+        CONNECTION,
+        UPGRADE
+    },
+    upgrade::Upgraded,
+    Body,
+    Method,
+    Request,
+    StatusCode,
+    Uri,
+};
+use hyperlocal::UnixConnector;
+use mime::Mime;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use shiplift::{
+    errors::Error,
+    transport::Transport,
+};
+use std::{
+    collections::{
+        BTreeMap,
+        VecDeque
+    },
+    env,
+};
+use tokio::io::{
+    AsyncRead,
+    AsyncReadExt
+};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+type Headers = Vec<(&'static str, String)>;
+
+pub struct Engine {
+    transport: Transport,
+}
+
+impl Engine {
+    /// Connects to `DOCKER_HOST`, falling back on unix:///var/run/docker.sock
+    pub fn new() -> Engine {
+        let host = env::var("DOCKER_HOST").unwrap_or_else(|_| "unix:///var/run/docker.sock".to_string());
+        let transport = match host.strip_prefix("unix://") {
+            Some(path) => Transport::Unix {
+                client: Client::builder()
+                    .pool_max_idle_per_host(0)
+                    .build(UnixConnector),
+                path: path.to_string(),
+            },
+            None => Transport::Tcp {
+                client: Client::builder().build(HttpConnector::new()),
+                host: host.replacen("tcp://", "http://", 1).trim_end_matches('/').to_string(),
+            },
+        };
+        Engine { transport }
+    }
+
+    pub async fn get_json<T: DeserializeOwned>(&self, endpoint: &str) -> Result<T> {
+        let raw = self.request(Method::GET, endpoint, None, None).await?;
+        Ok(serde_json::from_str(&raw)?)
+    }
+
+    pub async fn post_json<T: DeserializeOwned>(&self, endpoint: &str, body: Option<(Body, Mime)>) -> Result<T> {
+        let raw = self.request(Method::POST, endpoint, body, None).await?;
+        Ok(serde_json::from_str(&raw)?)
+    }
+
+    pub async fn delete_json<T: DeserializeOwned>(&self, endpoint: &str) -> Result<T> {
+        let raw = self.request(Method::DELETE, endpoint, None, None).await?;
+        Ok(serde_json::from_str(&raw)?)
+    }
+
+    /// Sends a DELETE whose response carries no body
+    pub async fn delete(&self, endpoint: &str) -> Result<()> {
+        self.request(Method::DELETE, endpoint, None, None).await.map(|_| ())
+    }
+
+    /// Streams the raw response chunks of a request
+    pub fn stream<'a>(
+        &'a self,
+        method: Method,
+        endpoint: String,
+        body: Option<(Body, Mime)>,
+        headers: Option<Headers>,
+    ) -> impl Stream<Item = Result<Bytes>> + Unpin + 'a {
+        Box::pin(self.transport.stream_chunks(method, endpoint, body, headers))
+    }
+
+    /// Streams the JSON messages of a request, such as build or pull progress
+    pub fn stream_values<'a>(
+        &'a self,
+        method: Method,
+        endpoint: String,
+        body: Option<(Body, Mime)>,
+        headers: Option<Headers>,
+    ) -> impl Stream<Item = Result<Value>> + Unpin + 'a {
+        json_values(self.stream(method, endpoint, body, headers))
+    }
+
+    /// Sends a POST that switches the connection to `protocol` and returns the raw connection
+    pub async fn upgrade(&self, endpoint: &str, protocol: &str, headers: Headers) -> Result<Upgraded> {
+        let request = |uri: Uri| {
+            let mut builder = Request::post(uri).header(UPGRADE, protocol).header(CONNECTION, "Upgrade");
+            for (name, value) in &headers {
+                builder = builder.header(*name, value);
+            }
+            builder.body(Body::empty())
+        };
+        let response = match &self.transport {
+            Transport::Unix { client, path } => client.request(request(hyperlocal::Uri::new(path, endpoint).into())?).await?,
+            Transport::Tcp { client, host } => client.request(request(format!("{}{}", host, endpoint).parse()?)?).await?,
+            _ => return Err(Error::ConnectionNotUpgraded),
+        };
+        if response.status() != StatusCode::SWITCHING_PROTOCOLS {
+            let status = response.status();
+            let body = hyper::body::to_bytes(response.into_body()).await?;
+            let message = serde_json::from_slice::<Value>(&body)
+                .ok()
+                .and_then(|value| value["message"].as_str().map(str::to_string))
+                .unwrap_or_else(|| String::from_utf8_lossy(&body).trim().to_string());
+            return Err(Error::Fault { code: status, message });
+        }
+        Ok(hyper::upgrade::on(response).await?)
+    }
+
+    async fn request(
+        &self,
+        method: Method,
+        endpoint: &str,
+        body: Option<(Body, Mime)>,
+        headers: Option<Headers>,
+    ) -> Result<String> {
+        self.transport.request(method, endpoint, body, headers).await
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Decodes a chunked body into JSON values.
+///
+/// The daemon writes one message per line, but a message may be split across
+/// chunks, so undecoded bytes are carried over to the next chunk.
+fn json_values<'a, S>(chunks: S) -> impl Stream<Item = Result<Value>> + Unpin + 'a
+where
+    S: Stream<Item = Result<Bytes>> + Unpin + 'a,
+{
+    let state = (chunks, Vec::new(), VecDeque::new(), false);
+    Box::pin(stream::unfold(state, |(mut chunks, mut buffer, mut ready, mut done)| async move {
+        loop {
+            if let Some(item) = ready.pop_front() {
+                return Some((item, (chunks, buffer, ready, done)));
+            }
+            if done {
+                return None;
+            }
+            match chunks.next().await {
+                Some(Ok(bytes)) => buffer.extend_from_slice(&bytes),
+                Some(Err(e)) => {
+                    ready.push_back(Err(e));
+                    done = true;
+                    continue;
+                }
+                None => done = true,
+            }
+            let mut values = serde_json::Deserializer::from_slice(&buffer).into_iter::<Value>();
+            let mut consumed = 0;
+            loop {
+                match values.next() {
+                    Some(Ok(value)) => {
+                        consumed = values.byte_offset();
+                        ready.push_back(Ok(value));
+                    }
+                    Some(Err(e)) if e.is_eof() && !done => break,
+                    Some(Err(e)) => {
+                        ready.push_back(Err(Error::from(e)));
+                        consumed = buffer.len();
+                        break;
+                    }
+                    None => {
+                        consumed = buffer.len();
+                        break;
+                    }
+                }
+            }
+            buffer.drain(..consumed);
+        }
+    }))
+}
+
+/// Appends URL-encoded query parameters to an endpoint
+pub fn query<'a, I>(endpoint: &str, params: I) -> String
+where
+    I: IntoIterator<Item = (&'a str, String)>,
+{
+    let encoded = url::form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(params)
+        .finish();
+    if encoded.is_empty() {
+        endpoint.to_string()
+    }
+    else {
+        format!("{}?{}", endpoint, encoded)
+    }
+}
+
+/// Turns `key=value` filter flags into the JSON `filters` query parameter
+pub fn filters(flags: &[String]) -> std::result::Result<String, String> {
+    let mut map: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for flag in flags {
+        match flag.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => {
+                map.entry(key.trim().to_lowercase()).or_default().push(value.to_string())
+            }
+            _ => return Err(format!("bad format of filter (expected name=value): {}", flag)),
+        }
+    }
+    Ok(serde_json::to_string(&map).unwrap())
+}
+
+/// The daemon's own message for a failed request, or the error itself
+pub fn error_message(e: &Error) -> String {
+    match e {
+        Error::Fault { message, .. } => format!("Error response from daemon: {}", message),
+        _ => e.to_string(),
+    }
+}
+
+/// A request body read from `reader` chunk by chunk instead of buffered whole
+pub fn body_from_reader<R>(reader: R) -> Body
+where
+    R: AsyncRead + Send + Unpin + 'static,
+{
+    Body::wrap_stream(read_chunks(reader))
+}
+
+/// The contents of `reader` as a stream of chunks
+pub fn read_chunks<R>(reader: R) -> impl Stream<Item = std::io::Result<Bytes>> + Send
+where
+    R: AsyncRead + Send + Unpin + 'static,
+{
+    stream::unfold(reader, |mut reader| async move {
+        let mut chunk = Vec::with_capacity(64 * 1024);
+        match reader.read_buf(&mut chunk).await {
+            Ok(0) => None,
+            Ok(_) => Some((Ok(Bytes::from(chunk)), reader)),
+            Err(e) => Some((Err(e), reader)),
+        }
+    })
+}
+
+pub fn tar() -> Mime {
+    "application/x-tar".parse().unwrap()
+}
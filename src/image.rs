@@ -1,39 +1,145 @@
+use crate::engine::{
+    self,
+    Engine
+};
+use crate::{
+    buildkit,
+    context,
+    dockerfile,
+    pipe,
+    template,
+    term,
+    units
+};
 use futures::StreamExt;
+use hyper::{
+    body::Bytes,
+    Method
+};
+use serde::Deserialize;
+use serde_json::Value;
 use shiplift::{
+    ContainerOptions,
     PullOptions,
-    BuildOptions,
+    RmContainerOptions,
     Docker,
-    builder::BuildOptionsBuilder,
 };
 
 use clap::{
     Parser,
-    // ArgAction
+    ArgAction,
     Subcommand,
 };
+use std::{
+    fs,
+    io::{
+        self,
+        Read
+    },
+    path::{
+        Component,
+        Path,
+        PathBuf
+    },
+    process
+};
+
+/// Instructions the daemon accepts through `--change` on import and commit
+const CHANGE_INSTRUCTIONS: [&str; 9] = ["CMD", "ENTRYPOINT", "ENV", "EXPOSE", "LABEL", "ONBUILD", "USER", "VOLUME", "WORKDIR"];
 
  # [derive(Subcommand, Debug)]
 pub enum ImageCommand {
     ///Build an image from a Dockerfile
     Build {},
     ///       Show the history of an image
-    History {},
+    History {
+        /// Image name or ID
+        image: String,
+
+        /// Format output: 'table' or 'json'
+         # [arg(long)]
+        format: Option < String > ,
+
+        /// Print sizes and dates in human readable format (default true)
+         # [arg(short = 'H', long, default_value_t = true, action = ArgAction::Set, num_args = 0..=1, default_missing_value = "true")]
+        human: bool,
+
+        /// Don't truncate output
+         # [arg(long)]
+        no_trunc: bool,
+
+        /// Only show image IDs
+         # [arg(short, long)]
+        quiet: bool,
+    },
     ///  Import the contents from a tarball to create a filesystem image
-    Import {},
+    Import {
+        /// Tarball file, URL, or - to read the tarball from STDIN
+         # [arg(value_name = "file|URL|-")]
+        source: String,
+
+        /// Repository name and optionally a tag for the new image
+         # [arg(value_name = "REPOSITORY[:TAG]")]
+        repository: Option < String > ,
+
+        /// Apply Dockerfile instruction to the created image
+         # [arg(short, long, value_name = "list")]
+        change: Option < Vec < String >> ,
+
+        /// Set commit message for imported image
+         # [arg(short, long)]
+        message: Option < String > ,
+
+        /// Set platform if server is multi-platform capable
+         # [arg(long)]
+        platform: Option < String > ,
+    },
     ///       Display detailed information on one or more images
-    Inspect {},
+    Inspect {
+        /// Image names or IDs
+         # [arg(required = true)]
+        images: Vec < String > ,
+
+        /// Format output using a custom template
+         # [arg(short, long)]
+        format: Option < String > ,
+    },
     ///          Load an image from a tar archive or STDIN
     Load {},
     ///           List images
     Ls {},
     ///        Remove unused images
-    Prune {},
+    Prune {
+        /// Remove all unused images, not just dangling ones
+         # [arg(short, long)]
+        all: bool,
+
+        /// Provide filter values (e.g. "until=24h", "label=key=value")
+         # [arg(long, value_name = "filter")]
+        filter: Option < Vec < String >> ,
+
+        /// Do not prompt for confirmation
+         # [arg(short, long)]
+        force: bool,
+    },
     ///          Pull an image or a repository from a registry
     Pull {},
     ///         Push an image or a repository to a registry
     Push {},
     ///           Remove one or more images
-    Rm {},
+    Rm {
+        /// Images to delete
+         # [arg(required = true)]
+        images: Vec < String > ,
+
+        /// Force removal of the image
+         # [arg(short, long)]
+        force: bool,
+
+        /// Do not delete untagged parents
+         # [arg(long)]
+        no_prune: bool,
+    },
     /// Save one or more images to a tar archive (streamed to STDOUT by default)
     Save {},
     /// Create a tag TARGET_IMAGE that refers to SOURCE_IMAGE
@@ -41,238 +147,792 @@ pub enum ImageCommand {
 }
 
 pub async fn handle_image_command(command:  & ImageCommand) {
-    /*
     match command {
-    ContainerCommand::Attach {
-    container,
-    detach_keys,
-    no_stdin,
-    sig_proxy,
-    }
-    => {
-    println!(
-    "Attaching to container: {} (detach_keys: {:?}, no_stdin: {}, sig_proxy: {})",
-    container, detach_keys, no_stdin, sig_proxy);
-    // 在这里处理 Attach 逻辑
-    }
-    ContainerCommand::Commit {
-    container,
-    repository,
-    message,
-    }
-    => {
-    println!(
-    "Committing container: {} (repository: {:?}, message: {:?})",
-    container, repository, message);
-    // 在这里处理 Commit 逻辑
-    }
-    ContainerCommand::Cp {
-    src,
-    dest
-    }
-    => {
-    println!("Copying from {} to {}", src, dest);
-    // 在这里处理 Cp 逻辑
-    }
-    ContainerCommand::Create {
-    image,
-    command,
-    arguments,
-    options,
-    } => {
-    println!("Running container with image: {}", image);
-    create(image, options.clone()).await;
-    }
+        ImageCommand::Import {
+            source,
+            repository,
+            change,
+            message,
+            platform,
+        }
+         => {
+            import(source, repository.clone(), change.clone().unwrap_or_default(), message.clone(), platform.clone()).await;
+        }
 
-    ContainerCommand::Diff {
-    container
-    }
-    => {
-    println!("Inspecting changes to container: {}", container);
-    // 在这里处理 Diff 逻辑
-    }
-    ContainerCommand::Exec {
-    container,
-    command
-    }
-    => {
-    println!("Executing command '{}' in container: {}", command, container);
-    // 在这里处理 Exec 逻辑
-    }
-    ContainerCommand::Logs {
-    container,
-    }
-    => {
-    println!("Get logs of container: {}", container);
-    logs(container.to_string()).await;
-    }
-    // 可以继续添加其他命令的处理逻辑
-    ContainerCommand::Export {
-    container,
-    }
-    => {
-    // println!("exporting container '{:?}' : {}", command, container);
-    export(container.to_string()).await;
-    }
+        ImageCommand::History {
+            image,
+            format,
+            human,
+            no_trunc,
+            quiet,
+        }
+         => {
+            history(image, format.as_deref(), *human, *no_trunc, *quiet).await;
+        }
 
-    _ => println!("Command not implemented yet."),
+        ImageCommand::Inspect {
+            images,
+            format,
+        }
+         => {
+            template::inspect("/images", images, format.as_deref()).await;
+        }
+
+        ImageCommand::Prune {
+            all,
+            filter,
+            force,
+        }
+         => {
+            prune(*all, filter.clone().unwrap_or_default(), *force).await;
+        }
+
+        ImageCommand::Rm {
+            images,
+            force,
+            no_prune,
+        }
+         => {
+            rmi(images, *force, *no_prune).await;
+        }
+
+        _ => println!("Command not implemented yet."),
     }
-     */
 }
 
  # [derive(Parser, Debug, Clone)]
 pub struct BuildArgOptions {
-    /*
     /// Add a custom host-to-IP mapping (host:ip)
-    #[arg(long, value_name = "list")]
-    add_host: Option<Vec<String>>,
+     # [arg(long, value_name = "list")]
+    add_host: Option < Vec < String >> ,
 
     /// Set build-time variables
-    #[arg(long, value_name = "list")]
-    build_arg: Option<Vec<String>>,
+     # [arg(long, value_name = "list")]
+    pub build_arg: Option < Vec < String >> ,
 
     /// Images to consider as cache sources
-    #[arg(long, value_name = "strings")]
-    cache_from: Option<Vec<String>>,
+     # [arg(long, value_name = "strings")]
+    pub cache_from: Option < Vec < String >> ,
 
     /// Optional parent cgroup for the container
-    #[arg(long, value_name = "string")]
-    cgroup_parent: Option<String>,
+     # [arg(long, value_name = "string")]
+    cgroup_parent: Option < String > ,
 
     /// Compress the build context using gzip
-    #[arg(long, action = ArgAction::SetTrue)]
+     # [arg(long, action = ArgAction::SetTrue)]
     compress: bool,
 
     /// Limit the CPU CFS period
-    #[arg(long, value_name = "int")]
-    cpu_period: Option<u64>,
+     # [arg(long, value_name = "int")]
+    cpu_period: Option < u64 > ,
 
     /// Limit the CPU CFS quota
-    #[arg(long, value_name = "int")]
-    cpu_quota: Option<u64>,
+     # [arg(long, value_name = "int")]
+    cpu_quota: Option < i64 > ,
 
     /// CPU shares (relative weight)
-    #[arg(short = 'c', long, value_name = "int")]
-    cpu_shares: Option<u64>,
+     # [arg(short = 'c', long, value_name = "int")]
+    cpu_shares: Option < u64 > ,
 
     /// CPUs in which to allow execution (0-3, 0,1)
-    #[arg(long, value_name = "string")]
-    cpuset_cpus: Option<String>,
+     # [arg(long, value_name = "string")]
+    cpuset_cpus: Option < String > ,
 
     /// MEMs in which to allow execution (0-3, 0,1)
-    #[arg(long, value_name = "string")]
-    cpuset_mems: Option<String>,
-
+     # [arg(long, value_name = "string")]
+    cpuset_mems: Option < String > ,
+    /*
     /// Skip image verification (default true)
     #[arg(long, action = ArgAction::SetTrue)]
     disable_content_trust: bool,
-
+     */
     /// Name of the Dockerfile (Default is 'PATH/Dockerfile')
-    #[arg(short = 'f', long, value_name = "string")]
-    file: Option<String>,
+     # [arg(short = 'f', long, value_name = "string")]
+    pub file: Option < String > ,
 
     /// Always remove intermediate containers
-    #[arg(long, action = ArgAction::SetTrue)]
+     # [arg(long, action = ArgAction::SetTrue)]
     force_rm: bool,
 
     /// Write the image ID to the file
-    #[arg(long, value_name = "string")]
-    iidfile: Option<String>,
+     # [arg(long, value_name = "string")]
+    iidfile: Option < String > ,
 
     /// Container isolation technology
-    #[arg(long, value_name = "string")]
-    isolation: Option<String>,
+     # [arg(long, value_name = "string")]
+    isolation: Option < String > ,
 
     /// Set metadata for an image
-    #[arg(long, value_name = "list")]
-    label: Option<Vec<String>>,
+     # [arg(long, value_name = "list")]
+    pub label: Option < Vec < String >> ,
 
     /// Memory limit
-    #[arg(short = 'm', long, value_name = "bytes")]
-    memory: Option<String>,
+     # [arg(short = 'm', long, value_name = "bytes")]
+    memory: Option < String > ,
 
     /// Swap limit equal to memory plus swap: '-1' to enable unlimited swap
-    #[arg(long, value_name = "bytes")]
-    memory_swap: Option<String>,
+     # [arg(long, value_name = "bytes", allow_hyphen_values = true)]
+    memory_swap: Option < String > ,
 
     /// Set the networking mode for the RUN instructions during build (default "default")
-    #[arg(long, value_name = "string")]
-    network: Option<String>,
+     # [arg(long, value_name = "string")]
+    pub network: Option < String > ,
 
     /// Do not use cache when building the image
-    #[arg(long, action = ArgAction::SetTrue)]
-    no_cache: bool,
+     # [arg(long, action = ArgAction::SetTrue)]
+    pub no_cache: bool,
+
+    /// Output destination (format: "type=local,dest=path")
+     # [arg(short = 'o', long, value_name = "string")]
+    pub output: Option < String > ,
 
     /// Always attempt to pull a newer version of the image
-    #[arg(long, action = ArgAction::SetTrue)]
-    pull: bool,
+     # [arg(long, action = ArgAction::SetTrue)]
+    pub pull: bool,
 
     /// Suppress the build output and print image ID on success
-    #[arg(short = 'q', long, action = ArgAction::SetTrue)]
+     # [arg(short = 'q', long, action = ArgAction::SetTrue)]
     quiet: bool,
 
     /// Remove intermediate containers after a successful build (default true)
-    #[arg(long, action = ArgAction::SetTrue)]
+     # [arg(long, default_value_t = true, action = ArgAction::Set, num_args = 0..=1, default_missing_value = "true")]
     rm: bool,
 
+    /// Secret to expose to the build (format: "id=mysecret[,src=/local/secret]")
+     # [arg(long, value_name = "stringArray")]
+    secret: Option < Vec < String >> ,
+    /*
     /// Security options
     #[arg(long, value_name = "strings")]
     security_opt: Option<Vec<String>>,
-
-    /// Size of /dev/shm
-    #[arg(long, value_name = "bytes")]
-    shm_size: Option<String>,
      */
+    /// Size of /dev/shm
+     # [arg(long, value_name = "bytes")]
+    shm_size: Option < String > ,
+
+    /// SSH agent socket to expose to the build (format: "default|<id>[=<socket>]")
+     # [arg(long, value_name = "stringArray")]
+    ssh: Option < Vec < String >> ,
+
     /// Name and optionally a tag in the 'name:tag' format
      # [arg(short = 't', long, value_name = "list")]
-    tag: Option < String > ,
-    // tag: Option < Vec < String >> ,
-    /*
+    pub tag: Option < Vec < String >> ,
+
     /// Set the target build stage to build
-    #[arg(long, value_name = "string")]
-    target: Option<String>,
+     # [arg(long, value_name = "string")]
+    pub target: Option < String > ,
 
     /// Ulimit options
-    #[arg(long, value_name = "ulimit")]
-    ulimit: Option<Vec<String>>,
-     */
+     # [arg(long, value_name = "ulimit")]
+    ulimit: Option < Vec < String >> ,
+}
+
+impl Default for BuildArgOptions {
+    /// The options of a plain `rocker build`, for builds not started from the command line
+    fn default() -> Self {
+        BuildArgOptions::parse_from(["build"])
+    }
 }
 
 pub async fn build(path:  & str, build_options: BuildArgOptions) {
-    let docker = Docker::new();
-    // let path = env::args().nth(1).expect("You need to specify a path");
+    match build_image(path, &build_options, None).await {
+        Ok(Some(image_id)) if build_options.quiet => println!("{}", image_id),
+        Ok(_) => {}
+        Err(failure) => {
+            eprintln!("Error: {}", failure.message);
+            process::exit(failure.code);
+        }
+    }
+}
 
-    let mut options:  & mut BuildOptionsBuilder =  & mut BuildOptions::builder(path);
-    // let mut options = BuildOptionsBuilder::new(path);
-    if let Some(tag) = build_options.tag {
-        // let options = BuildOptions::builder(path).tag("shiplift_test").build();
-        // let options = BuildOptions::builder(path).tag(tag).build();
-        options = options.tag(tag);
+ # [derive(Deserialize, Debug)]
+ # [serde(rename_all = "PascalCase")]
+struct ListedImage {
+    id: String,
+}
+
+/// Why a build failed, and the status rocker should exit with
+pub struct BuildFailure {
+    pub message: String,
+    pub code: i32,
+}
+
+impl From<String> for BuildFailure {
+    fn from(message: String) -> Self {
+        BuildFailure { message, code: 1 }
     }
-    else {}
-    let final_options: BuildOptions = options.build();
+}
 
-    let mut stream = docker.images().build( & final_options);
+/// Runs a build and returns the ID of the image, if the daemon reported one.
+///
+/// With a `prefix`, each line of build output is labelled so concurrent builds stay readable.
+pub async fn build_image(path: &str, build_options: &BuildArgOptions, prefix: Option<&str>) -> Result<Option<String>, BuildFailure> {
+    let mut params = build_query(build_options)?;
+    let output = build_options.output.as_deref().map(parse_output).transpose()?;
+    let secrets = build_options.secret.iter().flatten().map(|spec| buildkit::parse_secret(spec)).collect::<Result<Vec<_>, _>>()?;
+    let agents = build_options.ssh.iter().flatten().map(|spec| buildkit::parse_ssh(spec)).collect::<Result<Vec<_>, _>>()?;
+    let context = context::prepare(path, build_options.file.as_deref(), build_options.compress, build_options.quiet).await?;
+    params.push(("dockerfile", context.dockerfile.clone()));
+    if let Some(iidfile) = &build_options.iidfile {
+        // Never leave the ID of an earlier build behind
+        if let Err(e) = fs::remove_file(iidfile) {
+            if e.kind() != io::ErrorKind::NotFound {
+                return Err(format!("removing image ID file: {}", e).into());
+            }
+        }
+    }
+    if build_options.quiet {
+        params.push(("q", "1".to_string()));
+    }
+
+    let engine = Engine::new();
+    // An untagged result only exists to be exported, unless -q or --iidfile hand its ID
+    // to the user. A cached build can return an image they already had, so remember
+    // what exists now and only remove an image this build created.
+    let tagged = build_options.tag.as_ref().is_some_and(|tags| !tags.is_empty());
+    let existing_images = if output.is_some() && !tagged && !build_options.quiet && build_options.iidfile.is_none() {
+        let images: Vec<ListedImage> = engine
+            .get_json("/images/json?all=1")
+            .await
+            .map_err(|e| format!("failed to list images: {}", engine::error_message(&e)))?;
+        Some(images.into_iter().map(|image| image.id).collect::<Vec<_>>())
+    }
+    else {
+        None
+    };
+    // Secrets and SSH agents are only available to BuildKit, through a session
+    let _session = if secrets.is_empty() && agents.is_empty() {
+        None
+    }
+    else {
+        let session = buildkit::Session::new(secrets, agents);
+        params.push(("version", "2".to_string()));
+        params.push(("session", session.id().to_string()));
+        Some(session.start(&engine).await?)
+    };
+    // The size is only known once the context has been streamed to the daemon
+    let quiet = build_options.quiet;
+    let label = prefix.map(str::to_string);
+    let body = context.into_body(move |size| {
+        if !quiet {
+            show_output(label.as_deref(), &format!("Sending build context to Docker daemon  {}\n", units::human_size(size as f64)));
+        }
+    });
+    let body = Some((body, engine::tar()));
+    let mut stream = engine.stream_values(Method::POST, engine::query("/build", params), body, None);
+    let mut image_id = None;
+    let mut trace = buildkit::TraceDisplay::default();
     while let Some(build_result) = stream.next().await {
-        match build_result {
-            Ok(output) => println!("{:?}", output),
-            Err(e) => eprintln!("Error: {}", e),
+        let message: BuildMessage = build_result
+            .and_then(|value| Ok(serde_json::from_value(value)?))
+            .map_err(|e| engine::error_message(&e))?;
+        if let Some(detail) = message.error_detail {
+            return Err(BuildFailure {
+                message: detail.message,
+                code: if detail.code > 0 { detail.code } else { 1 },
+            });
+        }
+        if let Some(error) = message.error {
+            return Err(error.into());
+        }
+        if message.id.as_deref() == Some("moby.buildkit.trace") {
+            if let Some(aux) = message.aux.as_ref().and_then(Value::as_str) {
+                for line in trace.lines(aux) {
+                    if !build_options.quiet {
+                        show_output(prefix, &format!("{}\n", line));
+                    }
+                }
+            }
+            continue;
+        }
+        if let Some(id) = message.aux.as_ref().and_then(|aux| aux["ID"].as_str()) {
+            image_id = Some(id.to_string());
+        }
+        if let Some(text) = message.stream {
+            // Daemons that predate the aux message only announce the ID in the output
+            if let Some(id) = text.trim().strip_prefix("Successfully built ") {
+                image_id.get_or_insert_with(|| id.to_string());
+            }
+            if !build_options.quiet {
+                show_output(prefix, &text);
+            }
+        }
+        else if let Some(status) = message.status {
+            // Per-layer download and extraction progress is too noisy line by line
+            if !build_options.quiet && message.progress_detail.is_none_or(|detail| detail.is_empty()) {
+                match message.id {
+                    Some(id) => show_output(prefix, &format!("{}: {}\n", id, status)),
+                    None => show_output(prefix, &format!("{}\n", status)),
+                }
+            }
         }
     }
+
+    let image_id = match image_id {
+        Some(image_id) => image_id,
+        None if build_options.quiet || build_options.iidfile.is_some() || output.is_some() => {
+            return Err("the daemon did not report the ID of the built image".to_string().into());
+        }
+        None => return Ok(None),
+    };
+    if let Some(iidfile) = &build_options.iidfile {
+        fs::write(iidfile, &image_id).map_err(|e| format!("failed to write image ID to {}: {}", iidfile, e))?;
+    }
+    if let Some(output) = output {
+        // Older daemons only report the short ID
+        let short = term::short_id(&image_id);
+        let keep_image = existing_images.is_none_or(|images| images.iter().any(|id| term::short_id(id) == short));
+        export_output(&image_id, &output, keep_image).await?;
+    }
+    Ok(Some(image_id))
+}
+
+fn show_output(prefix: Option<&str>, text: &str) {
+    match prefix {
+        Some(prefix) => {
+            for line in text.lines() {
+                println!("[{}] {}", prefix, line);
+            }
+        }
+        None => print!("{}", text),
+    }
 }
 
-pub async fn rmi(image: String) {
+ # [derive(Clone, Copy)]
+enum OutputType {
+    Local,
+    Tar,
+}
+
+/// Where `--output` puts the built filesystem
+struct BuildOutput {
+    kind: OutputType,
+    dest: PathBuf,
+    /// Path inside the image to export
+    src: String,
+}
+
+/// Parses `type=local,dest=DIR`, `type=tar,dest=FILE` or a bare directory
+fn parse_output(value: &str) -> Result<BuildOutput, String> {
+    if !value.contains('=') {
+        return Ok(BuildOutput {
+            kind: OutputType::Local,
+            dest: PathBuf::from(value),
+            src: "/".to_string(),
+        });
+    }
+    let mut kind = None;
+    let mut dest = None;
+    let mut src = "/".to_string();
+    for field in value.split(',') {
+        match field.split_once('=') {
+            Some(("type", "local")) => kind = Some(OutputType::Local),
+            Some(("type", "tar")) => kind = Some(OutputType::Tar),
+            Some(("type", other)) => return Err(format!("unsupported output type: {}", other)),
+            Some(("dest", path)) if !path.is_empty() => dest = Some(PathBuf::from(path)),
+            Some(("src", path)) if path.starts_with('/') => src = path.to_string(),
+            Some(("src", path)) => return Err(format!("output src must be an absolute path: {}", path)),
+            _ => return Err(format!("invalid output field: {}", field)),
+        }
+    }
+    let kind = kind.ok_or_else(|| format!("output type is required: {}", value))?;
+    let dest = dest.ok_or_else(|| format!("output dest is required: {}", value))?;
+    Ok(BuildOutput { kind, dest, src })
+}
+
+/// Copies the built filesystem out of a temporary container
+async fn export_output(image_id: &str, output: &BuildOutput, keep_image: bool) -> Result<(), String> {
     let docker = Docker::new();
-    // let img = env::args().nth(1).expect("You need to specify an image name");
+    // The command is never run, it only lets images without CMD be created
+    let options = ContainerOptions::builder(image_id).cmd(vec!["true"]).build();
+    let created = docker
+        .containers()
+        .create(&options)
+        .await
+        .map_err(|e| format!("failed to create temporary container: {}", engine::error_message(&e)))?;
+    let container = docker.containers().get(&created.id);
+
+    // The archive is written out as it arrives rather than held in memory
+    let (sender, mut archive) = pipe::reader();
+    let (kind, src, dest) = (output.kind, output.src.clone(), output.dest.clone());
+    let written = tokio::task::spawn_blocking(move || match kind {
+        OutputType::Tar => fs::File::create(&dest)
+            .and_then(|mut file| io::copy(&mut archive, &mut file))
+            .map(drop)
+            .map_err(|e| format!("failed to write {}: {}", dest.display(), e)),
+        OutputType::Local => unpack_output(archive, &src, &dest),
+    });
+    let mut copied = Ok(());
+    let mut stream = Box::pin(container.copy_from(Path::new(&output.src)));
+    while let Some(chunk) = stream.next().await {
+        match chunk {
+            Ok(chunk) => {
+                // The writer only hangs up after failing, and reports why below
+                if sender.send(Bytes::from(chunk)).await.is_err() {
+                    break;
+                }
+            }
+            Err(e) => {
+                copied = Err(format!("failed to copy {} from the image: {}", output.src, engine::error_message(&e)));
+                break;
+            }
+        }
+    }
+    drop(sender);
+    let written = written.await.map_err(|e| format!("failed to export to {}: {}", output.dest.display(), e))?;
+
+    let removed = container.remove(RmContainerOptions::builder().force(true).volumes(true).build()).await;
+    if !keep_image {
+        // Without force, the daemon refuses to remove an image that is still in use
+        if let Err(e) = Engine::new().delete_json::<Vec<DeleteItem>>(&format!("/images/{}", image_id)).await {
+            eprintln!("Warning: failed to remove intermediate image {}: {}", term::short_id(image_id), engine::error_message(&e));
+        }
+    }
+    copied?;
+    removed.map_err(|e| format!("failed to remove temporary container {}: {}", term::short_id(&created.id), engine::error_message(&e)))?;
+    written
+}
+
+/// Unpacks an archive API tarball so the contents of `src` land directly in `dest`.
+///
+/// Like `Entry::unpack_in`, nothing is written outside `dest`: hard links are resolved
+/// inside it and symlinks are never followed on the way to an entry.
+fn unpack_output(archive: impl Read, src: &str, dest: &Path) -> Result<(), String> {
+    let failed = |e: io::Error| format!("failed to export to {}: {}", dest.display(), e);
+    fs::create_dir_all(dest).map_err(failed)?;
+    // The archive API roots entries at the base name of the requested path
+    let base = src.trim_end_matches('/').rsplit('/').next().unwrap_or_default();
+    let strip_base = |name: &Path, is_file: bool| -> Result<PathBuf, String> {
+        let name = name.to_string_lossy();
+        let name = name.trim_start_matches("./").trim_end_matches('/');
+        let relative = match name.strip_prefix(base) {
+            _ if base.is_empty() => name,
+            Some("") if is_file => name,
+            Some(rest) if rest.is_empty() || rest.starts_with('/') => rest.trim_start_matches('/'),
+            _ => name,
+        };
+        let relative = PathBuf::from(relative);
+        if relative.components().any(|c| !matches!(c, Component::Normal(_))) {
+            return Err(format!("refusing to export unsafe path: {}", relative.display()));
+        }
+        Ok(relative)
+    };
+    let mut entries = tar::Archive::new(archive);
+    for entry in entries.entries().map_err(failed)? {
+        let mut entry = entry.map_err(failed)?;
+        let kind = entry.header().entry_type();
+        let relative = strip_base(&entry.path().map_err(failed)?, !kind.is_dir())?;
+        if relative.as_os_str().is_empty() {
+            continue;
+        }
+        create_parents(dest, &relative).map_err(failed)?;
+        let target = dest.join(&relative);
+        if !kind.is_dir() {
+            // Replace rather than write through whatever is already there
+            match fs::symlink_metadata(&target) {
+                Ok(metadata) if !metadata.is_dir() => fs::remove_file(&target).map_err(failed)?,
+                _ => {}
+            }
+        }
+        if kind.is_hard_link() {
+            let link = entry
+                .link_name()
+                .map_err(failed)?
+                .ok_or_else(|| format!("hard link without a target: {}", relative.display()))?;
+            let original = strip_base(&link, true)?;
+            create_parents(dest, &original).map_err(failed)?;
+            fs::hard_link(dest.join(original), &target).map_err(failed)?;
+        }
+        else {
+            entry.unpack(&target).map_err(failed)?;
+        }
+    }
+    Ok(())
+}
+
+/// Creates the directories leading to `relative` inside `dest`, refusing to pass through symlinks
+fn create_parents(dest: &Path, relative: &Path) -> io::Result<()> {
+    let mut dir = dest.to_path_buf();
+    for component in relative.parent().into_iter().flat_map(Path::components) {
+        dir.push(component);
+        match fs::symlink_metadata(&dir) {
+            Ok(metadata) if metadata.is_dir() => {}
+            Ok(_) => {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} is not a directory", dir.display())));
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => fs::create_dir(&dir)?,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
 
-    let img = image;
-    match docker.images().get( & img).delete().await {
-        Ok(statuses) => {
-            for status in statuses {
-                println!("{:?}", status);
+/// One event of the `/build` JSON stream
+ # [derive(Deserialize, Debug)]
+ # [serde(rename_all = "camelCase")]
+struct BuildMessage {
+    stream: Option<String>,
+    status: Option<String>,
+    id: Option<String>,
+    progress_detail: Option<serde_json::Map<String, Value>>,
+    error: Option<String>,
+    error_detail: Option<ErrorDetail>,
+    aux: Option<Value>,
+}
+
+ # [derive(Deserialize, Debug)]
+struct ErrorDetail {
+     # [serde(default)]
+    code: i32,
+    message: String,
+}
+
+/// Translates the build flags into `/build` query parameters
+fn build_query(options: &BuildArgOptions) -> Result<Vec<(&'static str, String)>, String> {
+    let mut params = Vec::new();
+    for tag in options.tag.iter().flatten() {
+        validate_tag(tag)?;
+        params.push(("t", tag.clone()));
+    }
+    if let Some(build_args) = &options.build_arg {
+        let mut args = serde_json::Map::new();
+        for arg in build_args {
+            match arg.split_once('=') {
+                Some((key, value)) => {
+                    args.insert(key.to_string(), Value::from(value));
+                }
+                // `--build-arg KEY` takes the value from the environment, if set
+                None => {
+                    if let Ok(value) = std::env::var(arg) {
+                        args.insert(arg.clone(), Value::from(value));
+                    }
+                }
+            }
+        }
+        params.push(("buildargs", Value::Object(args).to_string()));
+    }
+    if let Some(labels) = &options.label {
+        let labels: serde_json::Map<String, Value> = labels
+            .iter()
+            .map(|label| match label.split_once('=') {
+                Some((key, value)) => (key.to_string(), Value::from(value)),
+                None => (label.clone(), Value::from("")),
+            })
+            .collect();
+        params.push(("labels", Value::Object(labels).to_string()));
+    }
+    if let Some(hosts) = &options.add_host {
+        let mut extra_hosts = Vec::new();
+        for host in hosts {
+            match host.split_once([':', '=']) {
+                Some((name, ip)) if !name.is_empty() && !ip.is_empty() => extra_hosts.push(format!("{}:{}", name, ip)),
+                _ => return Err(format!("invalid argument \"{}\" for \"--add-host\" flag", host)),
             }
         }
-        Err(e) => eprintln!("Error: {}", e),
+        params.push(("extrahosts", extra_hosts.join(",")));
+    }
+    if let Some(cache_from) = &options.cache_from {
+        params.push(("cachefrom", serde_json::to_string(cache_from).unwrap()));
+    }
+    if let Some(ulimits) = &options.ulimit {
+        let ulimits = ulimits.iter().map(|u| parse_ulimit(u)).collect::<Result<Vec<Value>, String>>()?;
+        params.push(("ulimits", Value::Array(ulimits).to_string()));
+    }
+    if let Some(memory) = &options.memory {
+        params.push(("memory", units::parse_bytes(memory)?.to_string()));
+    }
+    if let Some(memory_swap) = &options.memory_swap {
+        let swap = if memory_swap == "-1" { -1 } else { units::parse_bytes(memory_swap)? };
+        params.push(("memswap", swap.to_string()));
+    }
+    if let Some(shm_size) = &options.shm_size {
+        params.push(("shmsize", units::parse_bytes(shm_size)?.to_string()));
+    }
+    let optional = [
+        ("target", options.target.clone()),
+        ("networkmode", options.network.clone()),
+        ("cgroupparent", options.cgroup_parent.clone()),
+        ("isolation", options.isolation.clone()),
+        ("cpusetcpus", options.cpuset_cpus.clone()),
+        ("cpusetmems", options.cpuset_mems.clone()),
+        ("cpushares", options.cpu_shares.map(|v| v.to_string())),
+        ("cpuperiod", options.cpu_period.map(|v| v.to_string())),
+        ("cpuquota", options.cpu_quota.map(|v| v.to_string())),
+    ];
+    params.extend(optional.into_iter().filter_map(|(key, value)| value.map(|v| (key, v))));
+    let flag = |set: bool| if set { "1".to_string() } else { "0".to_string() };
+    params.push(("nocache", flag(options.no_cache)));
+    params.push(("pull", flag(options.pull)));
+    params.push(("rm", flag(options.rm)));
+    params.push(("forcerm", flag(options.force_rm)));
+    Ok(params)
+}
+
+/// Checks that a build tag is a valid image reference without a digest
+fn validate_tag(tag: &str) -> Result<(), String> {
+    if tag.contains('@') {
+        return Err(format!("invalid argument \"{}\" for \"-t, --tag\" flag: build tag cannot contain a digest", tag));
+    }
+    validate_reference(tag).map_err(|e| format!("invalid argument \"{}\" for \"-t, --tag\" flag: {}", tag, e))
+}
+
+/// Validates `[domain/]path[:tag][@digest]` against the distribution reference grammar
+pub fn validate_reference(reference: &str) -> Result<(), String> {
+    let invalid = || "invalid reference format".to_string();
+    let (name, digest) = match reference.split_once('@') {
+        Some((name, digest)) => (name, Some(digest)),
+        None => (reference, None),
+    };
+    let (name, tag) = match name.rfind(':') {
+        Some(i) if !name[i..].contains('/') => (&name[..i], Some(&name[i + 1..])),
+        _ => (name, None),
+    };
+    if name.is_empty() {
+        return Err(invalid());
+    }
+    if name.len() > 255 {
+        return Err("repository name must not be more than 255 characters".to_string());
+    }
+
+    let mut components: Vec<&str> = name.split('/').collect();
+    let first = components[0];
+    if components.len() > 1 && (first.contains('.') || first.contains(':') || first == "localhost" || first.chars().any(|c| c.is_ascii_uppercase())) {
+        if !is_domain(first) {
+            return Err(invalid());
+        }
+        components.remove(0);
+    }
+    for component in components {
+        if component.chars().any(|c| c.is_ascii_uppercase()) && is_path_component(&component.to_lowercase()) {
+            return Err(format!("invalid reference format: repository name ({}) must be lowercase", name));
+        }
+        if !is_path_component(component) {
+            return Err(invalid());
+        }
+    }
+
+    if let Some(tag) = tag {
+        let valid = !tag.is_empty()
+            && tag.len() <= 128
+            && tag.chars().enumerate().all(|(i, c)| c.is_ascii_alphanumeric() || c == '_' || (i > 0 && (c == '.' || c == '-')));
+        if !valid {
+            return Err(invalid());
+        }
+    }
+    if let Some(digest) = digest {
+        let valid = match digest.split_once(':') {
+            Some((algorithm, hex)) => {
+                algorithm.starts_with(|c: char| c.is_ascii_alphabetic())
+                    && algorithm.chars().all(|c| c.is_ascii_alphanumeric() || "-_+.".contains(c))
+                    && hex.len() >= 32
+                    && hex.chars().all(|c| c.is_ascii_hexdigit())
+            }
+            None => false,
+        };
+        if !valid {
+            return Err(invalid());
+        }
+    }
+    Ok(())
+}
+
+/// `host[:port]`, where host is a dotted name or a bracketed IPv6 address
+fn is_domain(domain: &str) -> bool {
+    let (host, port) = if let Some(rest) = domain.strip_prefix('[') {
+        match rest.split_once(']') {
+            Some((ipv6, port)) => {
+                if ipv6.is_empty() || !ipv6.chars().all(|c| c.is_ascii_hexdigit() || c == ':') {
+                    return false;
+                }
+                ("ipv6", port.strip_prefix(':'))
+            }
+            None => return false,
+        }
+    }
+    else {
+        match domain.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (domain, None),
+        }
+    };
+    if let Some(port) = port {
+        if port.is_empty() || !port.chars().all(|c| c.is_ascii_digit()) {
+            return false;
+        }
+    }
+    host.split('.').all(|label| {
+        !label.is_empty()
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+    })
+}
+
+/// Lowercase alphanumerics separated by `.`, `_`, `__` or runs of `-`
+fn is_path_component(component: &str) -> bool {
+    let bytes = component.as_bytes();
+    if bytes.is_empty() || !bytes[0].is_ascii_lowercase() && !bytes[0].is_ascii_digit() {
+        return false;
+    }
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i].is_ascii_lowercase() || bytes[i].is_ascii_digit() {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < bytes.len() && !(bytes[i].is_ascii_lowercase() || bytes[i].is_ascii_digit()) {
+            i += 1;
+        }
+        let separator = &component[start..i];
+        let valid = separator == "." || separator == "_" || separator == "__" || separator.chars().all(|c| c == '-');
+        if !valid || i == bytes.len() {
+            return false;
+        }
+    }
+    true
+}
+
+/// Parses `name=soft[:hard]` into the JSON the daemon expects
+fn parse_ulimit(ulimit: &str) -> Result<Value, String> {
+    let invalid = || format!("invalid ulimit argument: {}", ulimit);
+    let (name, limits) = ulimit.split_once('=').ok_or_else(invalid)?;
+    let (soft, hard) = limits.split_once(':').unwrap_or((limits, limits));
+    let soft: i64 = soft.parse().map_err(|_| invalid())?;
+    let hard: i64 = hard.parse().map_err(|_| invalid())?;
+    if soft > hard {
+        return Err(format!("ulimit soft limit must be less than or equal to hard limit: {}", ulimit));
+    }
+    Ok(serde_json::json!({"Name": name, "Soft": soft, "Hard": hard}))
+}
+
+pub async fn rmi(images: &[String], force: bool, no_prune: bool) {
+    let engine = Engine::new();
+    let mut failed = false;
+    for image in images {
+        let endpoint = engine::query(
+            &format!("/images/{}", image),
+            vec![("force", force.to_string()), ("noprune", no_prune.to_string())],
+        );
+        match engine.delete_json::<Vec<DeleteItem>>(&endpoint).await {
+            Ok(items) => {
+                for item in items {
+                    if let Some(untagged) = item.untagged {
+                        println!("Untagged: {}", untagged);
+                    }
+                    if let Some(deleted) = item.deleted {
+                        println!("Deleted: {}", deleted);
+                    }
+                }
+            }
+            Err(e) => {
+                eprintln!("Error: {}", engine::error_message(&e));
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        process::exit(1);
     }
 }
 
@@ -296,3 +956,279 @@ pub async fn pull(name: String) {
         }
     }
 }
+
+pub async fn import(source: &str, repository: Option<String>, changes: Vec<String>, message: Option<String>, platform: Option<String>) {
+    for change in &changes {
+        match dockerfile::parse_instruction(change) {
+            Ok(instruction) if CHANGE_INSTRUCTIONS.contains(&instruction.keyword.as_str()) => {}
+            Ok(instruction) => {
+                eprintln!("Error: {} is not a valid change command", instruction.keyword);
+                process::exit(1);
+            }
+            Err(e) => {
+                eprintln!("Error: invalid change {:?}: {}", change, e);
+                process::exit(1);
+            }
+        }
+    }
+
+    let is_url = source.starts_with("http://") || source.starts_with("https://");
+    let mut params = vec![("fromSrc", if is_url { source.to_string() } else { "-".to_string() })];
+    if let Some(repository) = repository {
+        params.push(("repo", repository));
+    }
+    if let Some(message) = message {
+        params.push(("message", message));
+    }
+    if let Some(platform) = platform {
+        params.push(("platform", platform));
+    }
+    for change in changes {
+        params.push(("changes", change));
+    }
+
+    // The tarball is streamed to the daemon rather than read into memory first
+    let body = if is_url {
+        None
+    }
+    else if source == "-" {
+        Some((engine::body_from_reader(tokio::io::stdin()), engine::tar()))
+    }
+    else {
+        match tokio::fs::File::open(source).await {
+            Ok(file) => Some((engine::body_from_reader(file), engine::tar())),
+            Err(e) => {
+                eprintln!("Error: {}: {}", source, e);
+                process::exit(1);
+            }
+        }
+    };
+
+    let engine = Engine::new();
+    let mut stream = engine.stream_values(Method::POST, engine::query("/images/create", params), body, None);
+    while let Some(import_result) = stream.next().await {
+        match import_result {
+            Ok(output) => {
+                if let Some(error) = output.get("error").and_then(|e| e.as_str()) {
+                    eprintln!("Error: {}", error);
+                    process::exit(1);
+                }
+                match (output.get("status").and_then(|s| s.as_str()), output.get("progress").and_then(|p| p.as_str())) {
+                    (Some(status), Some(progress)) => println!("{} {}", status, progress),
+                    (Some(status), None) => println!("{}", status),
+                    _ => {}
+                }
+            }
+            Err(e) => {
+                eprintln!("Error: {}", engine::error_message(&e));
+                process::exit(1);
+            }
+        }
+    }
+}
+
+ # [derive(Deserialize, Debug)]
+ # [serde(rename_all = "PascalCase")]
+struct HistoryEntry {
+    id: String,
+    created: i64,
+    created_by: String,
+    size: i64,
+    comment: String,
+}
+
+pub async fn history(image: &str, format: Option<&str>, human: bool, no_trunc: bool, quiet: bool) {
+    let engine = Engine::new();
+    let entries: Vec<HistoryEntry> = match engine.get_json(&format!("/images/{}/history", image)).await {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("Error: {}", engine::error_message(&e));
+            process::exit(1);
+        }
+    };
+
+    let rows: Vec<Vec<String>> = entries
+        .iter()
+        .map(|entry| {
+            let id = if entry.id == "<missing>" || no_trunc {
+                entry.id.clone()
+            }
+            else {
+                term::short_id(&entry.id)
+            };
+            let created_at = chrono::DateTime::from_timestamp(entry.created, 0)
+                .map(|t| t.with_timezone(&chrono::Local).to_rfc3339())
+                .unwrap_or_default();
+            let created_by = entry.created_by.replace('\t', " ");
+            let created_by = if no_trunc {
+                created_by.trim().to_string()
+            }
+            else {
+                term::ellipsis(created_by.trim(), 45)
+            };
+            let size = if human {
+                units::human_size(entry.size as f64)
+            }
+            else {
+                entry.size.to_string()
+            };
+            vec![id, created_at, units::time_ago(entry.created), created_by, size, entry.comment.clone()]
+        })
+        .collect();
+
+    if quiet {
+        for row in rows {
+            println!("{}", row[0]);
+        }
+        return;
+    }
+    match format {
+        None | Some("table") => {
+            let rows: Vec<Vec<String>> = rows
+                .into_iter()
+                .map(|mut row| {
+                    let created_since = row.remove(2);
+                    if human {
+                        row[1] = created_since;
+                    }
+                    row
+                })
+                .collect();
+            term::print_table(&["IMAGE", "CREATED", "CREATED BY", "SIZE", "COMMENT"], &rows);
+        }
+        Some("json") => {
+            for row in rows {
+                let line = serde_json::json!({
+                    "ID": row[0],
+                    "CreatedAt": row[1],
+                    "CreatedSince": row[2],
+                    "CreatedBy": row[3],
+                    "Size": row[4],
+                    "Comment": row[5],
+                });
+                println!("{}", line);
+            }
+        }
+        Some(other) => {
+            eprintln!("Error: unsupported format: {}", other);
+            process::exit(1);
+        }
+    }
+}
+
+ # [derive(Deserialize, Debug)]
+ # [serde(rename_all = "PascalCase")]
+struct PruneReport {
+    images_deleted: Option<Vec<DeleteItem>>,
+    space_reclaimed: u64,
+}
+
+ # [derive(Deserialize, Debug)]
+ # [serde(rename_all = "PascalCase")]
+struct DeleteItem {
+    untagged: Option<String>,
+    deleted: Option<String>,
+}
+
+pub async fn prune(all: bool, mut filter: Vec<String>, force: bool) {
+    filter.push(format!("dangling={}", !all));
+    let filters = match engine::filters(&filter) {
+        Ok(filters) => filters,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    };
+
+    let warning = if all {
+        "WARNING! This will remove all images without at least one container associated to them."
+    }
+    else {
+        "WARNING! This will remove all dangling images."
+    };
+    if !force && !term::confirm(warning) {
+        return;
+    }
+
+    let engine = Engine::new();
+    let report: PruneReport = match engine.post_json(&engine::query("/images/prune", vec![("filters", filters)]), None).await {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("Error: {}", engine::error_message(&e));
+            process::exit(1);
+        }
+    };
+    let deleted = report.images_deleted.unwrap_or_default();
+    if !deleted.is_empty() {
+        println!("Deleted Images:");
+        for item in deleted {
+            if let Some(untagged) = item.untagged {
+                println!("untagged: {}", untagged);
+            }
+            if let Some(deleted) = item.deleted {
+                println!("deleted: {}", deleted);
+            }
+        }
+        println!();
+    }
+    println!("Total reclaimed space: {}", units::human_size(report.space_reclaimed as f64));
+}
+
+ # [cfg(test)]
+mod tests {
+    use super::unpack_output;
+    use std::fs;
+
+    fn header(kind: tar::EntryType, size: u64) -> tar::Header {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(kind);
+        header.set_size(size);
+        header.set_mode(0o755);
+        header
+    }
+
+    /// An archive API tarball of `/out`, with a hard link as the daemon writes them
+    fn archive(extra: impl FnOnce(&mut tar::Builder<Vec<u8>>)) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        builder.append_data(&mut header(tar::EntryType::Directory, 0), "out/", [].as_slice()).unwrap();
+        builder.append_data(&mut header(tar::EntryType::Regular, 2), "out/bin/app", b"hi".as_slice()).unwrap();
+        builder.append_link(&mut header(tar::EntryType::Link, 0), "out/app", "out/bin/app").unwrap();
+        extra(&mut builder);
+        builder.into_inner().unwrap()
+    }
+
+     # [test]
+    fn unpacks_hard_links_inside_dest() {
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("dest");
+        unpack_output(archive(|_| {}).as_slice(), "/out", &dest).unwrap();
+        assert_eq!(fs::read(dest.join("bin/app")).unwrap(), b"hi");
+        assert_eq!(fs::read(dest.join("app")).unwrap(), b"hi");
+        assert!(!dest.join("out").exists());
+    }
+
+     # [test]
+    fn refuses_to_write_through_symlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let outside = dir.path().join("outside");
+        fs::create_dir(&outside).unwrap();
+        let link_to = outside.clone();
+        let archive = archive(move |builder| {
+            builder.append_link(&mut header(tar::EntryType::Symlink, 0), "out/escape", &link_to).unwrap();
+            builder.append_data(&mut header(tar::EntryType::Regular, 2), "out/escape/file", b"no".as_slice()).unwrap();
+        });
+        let error = unpack_output(archive.as_slice(), "/out", &dir.path().join("dest")).unwrap_err();
+        assert!(error.contains("not a directory"), "{}", error);
+        assert!(!outside.join("file").exists());
+    }
+
+     # [test]
+    fn refuses_hard_links_out_of_dest() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = archive(|builder| {
+            builder.append_link(&mut header(tar::EntryType::Link, 0), "out/passwd", "../etc/passwd").unwrap();
+        });
+        let error = unpack_output(archive.as_slice(), "/out", &dir.path().join("dest")).unwrap_err();
+        assert!(error.contains("unsafe path"), "{}", error);
+    }
+}
@@ -1,6 +1,18 @@
+mod bake;
+mod builder;
+mod buildkit;
 mod container;
+mod context;
+mod dockerfile;
+mod engine;
 mod image;
+mod pipe;
 mod remote;
+mod signal;
+mod stats;
+mod template;
+mod term;
+mod units;
 
 use shiplift::{
     Docker,
@@ -57,20 +69,98 @@ enum DockerCommand {
 
     /// Remove one or more containers
     Rm {
-        /// Container ID or name
-        container: String,
+        /// Container names or IDs
+         # [arg(required = true)]
+        containers: Vec < String > ,
 
-        /// Force the removal of a running container
+        /// Force the removal of a running container (uses SIGKILL)
          # [arg(short, long)]
         force: bool,
 
         /// Remove the specified link
          # [arg(short, long)]
-        link: Option < String > ,
+        link: bool,
 
         /// Remove anonymous volumes associated with the container
          # [arg(short, long)]
-        volumes: Option < String > ,
+        volumes: bool,
+    },
+
+    /// Kill one or more running containers
+    Kill {
+        /// Container names or IDs
+         # [arg(required = true)]
+        containers: Vec < String > ,
+
+        /// Signal to send to the container
+         # [arg(short, long, default_value = "KILL")]
+        signal: String,
+    },
+
+    /// List port mappings or a specific mapping for the container
+    Port {
+        /// Container name or ID
+        container: String,
+
+        /// Only show the mapping of this port
+         # [arg(value_name = "PRIVATE_PORT[/PROTO]")]
+        private_port: Option < String > ,
+    },
+
+    /// Display the running processes of a container
+    Top {
+        /// Container name or ID
+        container: String,
+
+        /// Options passed to ps, e.g. "aux" or "-eo pid,comm". As with docker, everything
+        /// after CONTAINER goes to ps, including flags such as --format
+         # [arg(trailing_var_arg = true, allow_hyphen_values = true, value_name = "ps OPTIONS")]
+        ps_args: Vec < String > ,
+
+        /// Format output using "table" or "json"; must come before CONTAINER
+         # [arg(long)]
+        format: Option < String > ,
+    },
+
+    /// Update configuration of one or more containers
+    Update {
+        /// Container names or IDs
+         # [arg(required = true)]
+        containers: Vec < String > ,
+
+        /// Update options
+         # [clap(flatten)]
+        options: container::UpdateArgOptions,
+    },
+
+    /// Block until one or more containers stop, then print their exit codes
+    Wait {
+        /// Container names or IDs
+         # [arg(required = true)]
+        containers: Vec < String > ,
+
+        /// Wait until the container reaches this state
+         # [arg(long, default_value = "not-running", value_parser = ["not-running", "next-exit", "removed"])]
+        condition: String,
+    },
+
+    /// Start one or more stopped containers
+    Start {
+        /// Container names or IDs
+         # [arg(required = true)]
+        containers: Vec < String > ,
+
+        /// Attach STDOUT/STDERR
+         # [arg(short, long)]
+        attach: bool,
+
+        /// Override the key sequence for detaching a container
+         # [arg(long, value_name = "string")]
+        detach_keys: Option < String > ,
+
+        /// Attach container's STDIN
+         # [arg(short, long)]
+        interactive: bool,
     },
 
     /// Pull an image or a repository from a registry
@@ -136,11 +226,41 @@ enum DockerCommand {
         options: image::BuildArgOptions,
     },
 
+    /// Build several targets from a bake file
+    Bake {
+        /// Targets or groups to build (default "default")
+        targets: Vec < String > ,
+
+        /// Build definition file (default docker-bake.json or docker-bake.toml)
+         # [arg(short = 'f', long, value_name = "string")]
+        file: Option < String > ,
+
+        /// Print the resolved targets without building
+         # [arg(long)]
+        print: bool,
+
+        /// Do not use cache when building the images
+         # [arg(long)]
+        no_cache: bool,
+
+        /// Always attempt to pull newer versions of the base images
+         # [arg(long)]
+        pull: bool,
+    },
+
+    /// Manage the build cache
+     # [command(subcommand)]
+    Builder(builder::BuilderCommand),
+
+    /// Parse and lint Dockerfiles
+     # [command(subcommand)]
+    Dockerfile(dockerfile::DockerfileCommand),
+
     /// Remove one or more images
     Rmi {
-        /// image to delete
+        /// Images to delete
          # [arg(required = true)]
-        image: String,
+        images: Vec < String > ,
 
         /// Force removal of the image
          # [arg(short, long)]
@@ -218,9 +338,8 @@ async fn main() {
     match & cli.command {
         DockerCommand::Run {
             image,
-            command,
-            arguments,
             options,
+            ..
         }
          => {
             println!("Running container with image: {}", image);
@@ -233,18 +352,28 @@ async fn main() {
         }
          => {
             // 处理 `docker build` 的逻辑
-            println!("Building image from path or URL: {}", path_or_url);
             let cloned_options = options.clone();
             image::build(path_or_url, cloned_options).await;
 
         }
 
+        DockerCommand::Bake {
+            targets,
+            file,
+            print,
+            no_cache,
+            pull,
+        }
+         => {
+            bake::bake(targets.clone(), file.clone(), *print, *no_cache, *pull).await;
+        }
+
         DockerCommand::Ps {
             all
         }
          => {
             println!("Listing containers");
-            container::ps(all.clone()).await;
+            container::ps(*all).await;
         }
     DockerCommand::Images {
         all
@@ -266,24 +395,73 @@ DockerCommand::Image(image_command) => {
     image::handle_image_command(image_command).await;
 }
 
+DockerCommand::Builder(builder_command) => {
+    builder::handle_builder_command(builder_command).await;
+}
+
+DockerCommand::Dockerfile(dockerfile_command) => {
+    dockerfile::handle_dockerfile_command(dockerfile_command).await;
+}
+
 DockerCommand::Rm {
-    container,
+    containers,
     force,
-    volumes,
-    link
+    link,
+    volumes
 }
  => {
-    println!("Removing container: {}", container);
-    if  * force {
-        println!("Forcing container removal");
-    }
-if let Some(somevolumes) = volumes {
-    println!("volumes:{}", somevolumes);
+    container::rm(containers, *force, *link, *volumes).await;
+}
+
+DockerCommand::Kill {
+    containers,
+    signal
+}
+ => {
+    container::kill(containers, signal).await;
+}
+
+DockerCommand::Port {
+    container,
+    private_port
 }
-if let Some(link) = link {
-    println!("link:{}", link);
+ => {
+    container::port(container, private_port.as_deref()).await;
 }
 
+DockerCommand::Top {
+    container,
+    ps_args,
+    format
+}
+ => {
+    container::top(container, ps_args, format.as_deref()).await;
+}
+
+DockerCommand::Update {
+    containers,
+    options
+}
+ => {
+    container::update(containers, options).await;
+}
+
+DockerCommand::Wait {
+    containers,
+    condition
+}
+ => {
+    container::wait(containers, condition).await;
+}
+
+DockerCommand::Start {
+    containers,
+    attach,
+    detach_keys,
+    interactive
+}
+ => {
+    container::start(containers, *attach, detach_keys.as_deref(), *interactive).await;
 }
 
 DockerCommand::Pull {
@@ -342,19 +520,13 @@ DockerCommand::Info {}
 }
 
 DockerCommand::Rmi {
-    image,
+    images,
     force,
     no_prune
 }
  => {
     // 处理 rmi 命令
-    image::rmi(image.to_string()).await;
-    if  * force {
-        println!("Force removal of the image.");
-    }
-if  * no_prune {
-    println!("Do not delete untagged parents.");
-}
+    image::rmi(images, *force, *no_prune).await;
 }
 
 DockerCommand::Remote(remote_command) => {
@@ -0,0 +1,88 @@
+//! Byte pipes between async streams and blocking code such as tar packing.
+//!
+//! Archives can be far larger than memory, so they are handed over a chunk at a
+//! time through a bounded channel rather than collected first.
+
+use futures::{
+    stream,
+    Stream
+};
+use hyper::body::Bytes;
+use std::io::{
+    self,
+    BufWriter,
+    Read,
+    Write
+};
+use tokio::sync::mpsc;
+
+/// Chunks in flight before the sending side has to wait
+const CAPACITY: usize = 16;
+
+/// Size of the chunks written by [`produce`]
+const CHUNK: usize = 64 * 1024;
+
+/// Blocking reader over the chunks sent to the matching [`mpsc::Sender`]; dropping
+/// the sender ends the input.
+pub struct PipeReader {
+    receiver: mpsc::Receiver<Bytes>,
+    chunk: Bytes,
+}
+
+/// A channel to feed chunks into from async code, and a reader to use on the blocking pool
+pub fn reader() -> (mpsc::Sender<Bytes>, PipeReader) {
+    let (sender, receiver) = mpsc::channel(CAPACITY);
+    (sender, PipeReader { receiver, chunk: Bytes::new() })
+}
+
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.chunk.is_empty() {
+            match self.receiver.blocking_recv() {
+                Some(chunk) => self.chunk = chunk,
+                None => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.chunk.len());
+        buf[..n].copy_from_slice(&self.chunk.split_to(n));
+        Ok(n)
+    }
+}
+
+/// Runs `write` on the blocking pool and streams what it writes.
+///
+/// A failure ends the stream with its error; if the stream is dropped, writes fail
+/// with `BrokenPipe` so `write` can stop early.
+pub fn produce<F>(write: F) -> impl Stream<Item = io::Result<Bytes>> + Send
+where
+    F: FnOnce(&mut dyn Write) -> io::Result<()> + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel(CAPACITY);
+    let failed = sender.clone();
+    tokio::task::spawn_blocking(move || {
+        let mut writer = BufWriter::with_capacity(CHUNK, PipeWriter { sender });
+        let result = write(&mut writer).and_then(|_| writer.flush());
+        drop(writer);
+        if let Err(e) = result {
+            let _ = failed.blocking_send(Err(e));
+        }
+    });
+    stream::unfold(receiver, |mut receiver| async move { receiver.recv().await.map(|item| (item, receiver)) })
+}
+
+struct PipeWriter {
+    sender: mpsc::Sender<io::Result<Bytes>>,
+}
+
+impl Write for PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.sender
+            .blocking_send(Ok(Bytes::copy_from_slice(buf)))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "the reading side went away"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
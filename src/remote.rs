@@ -247,9 +247,7 @@ pub async fn handle_remote_command(command:  & RemoteCommand) {
         }
         RemoteCommand::Create {
             image,
-            command,
-            arguments,
-            options,
+            ..
         }
          => {
             println!("Running container with image: {}", image);
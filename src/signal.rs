@@ -0,0 +1,77 @@
+//! Linux signal names and numbers, as accepted by `--signal` flags.
+
+/// Names of the standard signals, without the `SIG` prefix
+const SIGNALS: [(&str, u32); 32] = [
+    ("ABRT", 6),
+    ("ALRM", 14),
+    ("BUS", 7),
+    ("CHLD", 17),
+    ("CLD", 17),
+    ("CONT", 18),
+    ("FPE", 8),
+    ("HUP", 1),
+    ("ILL", 4),
+    ("INT", 2),
+    ("IO", 29),
+    ("IOT", 6),
+    ("KILL", 9),
+    ("PIPE", 13),
+    ("POLL", 29),
+    ("PROF", 27),
+    ("PWR", 30),
+    ("QUIT", 3),
+    ("SEGV", 11),
+    ("STKFLT", 16),
+    ("STOP", 19),
+    ("SYS", 31),
+    ("TERM", 15),
+    ("TRAP", 5),
+    ("TSTP", 20),
+    ("TTIN", 21),
+    ("TTOU", 22),
+    ("URG", 23),
+    ("USR1", 10),
+    ("USR2", 12),
+    ("VTALRM", 26),
+    ("WINCH", 28),
+];
+
+const RTMIN: u32 = 34;
+const RTMAX: u32 = 64;
+
+/// Resolves `HUP`, `SIGHUP`, `SIGRTMIN+3` or `1` to a signal number
+pub fn parse(signal: &str) -> Result<u32, String> {
+    let invalid = || format!("invalid signal: {}", signal);
+    if let Ok(number) = signal.parse::<u32>() {
+        return if (1..=RTMAX).contains(&number) && number != 32 && number != 33 {
+            Ok(number)
+        }
+        else {
+            Err(invalid())
+        };
+    }
+
+    let upper = signal.to_uppercase();
+    let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+    if let Some(&(_, number)) = SIGNALS.iter().find(|(known, _)| *known == name) {
+        return Ok(number);
+    }
+    // Real-time signals are written relative to either end of their range
+    let offset = |rest: &str| rest.parse::<u32>().ok().filter(|n| *n <= RTMAX - RTMIN);
+    let number = match name {
+        "RTMIN" => Some(RTMIN),
+        "RTMAX" => Some(RTMAX),
+        _ => {
+            if let Some(rest) = name.strip_prefix("RTMIN+") {
+                offset(rest).map(|n| RTMIN + n)
+            }
+            else if let Some(rest) = name.strip_prefix("RTMAX-") {
+                offset(rest).map(|n| RTMAX - n)
+            }
+            else {
+                None
+            }
+        }
+    };
+    number.ok_or_else(invalid)
+}
@@ -0,0 +1,427 @@
+//! `rocker container stats`: live resource usage from the daemon's stats stream.
+
+use crate::{
+    engine::{
+        self,
+        Engine
+    },
+    template::Template,
+    term,
+    units,
+};
+use futures::{
+    future::join_all,
+    StreamExt
+};
+use hyper::Method;
+use serde::Deserialize;
+use serde_json::{
+    json,
+    Value
+};
+use std::{
+    collections::HashMap,
+    io::{
+        IsTerminal,
+        Write
+    },
+    process,
+    sync::{
+        Arc,
+        Mutex
+    },
+    time::Duration,
+};
+
+const DEFAULT_FORMAT: &str =
+    "table {{.Container}}\t{{.Name}}\t{{.CPUPerc}}\t{{.MemUsage}}\t{{.MemPerc}}\t{{.NetIO}}\t{{.BlockIO}}\t{{.PIDs}}";
+
+/// How often the live table is redrawn
+const REFRESH: Duration = Duration::from_millis(500);
+
+ # [derive(Deserialize, Debug, Default)]
+ # [serde(default)]
+struct Sample {
+    name: String,
+    id: String,
+    cpu_stats: CpuStats,
+    precpu_stats: CpuStats,
+    memory_stats: MemoryStats,
+    networks: Option<HashMap<String, NetworkStats>>,
+    blkio_stats: BlkioStats,
+    pids_stats: PidsStats,
+}
+
+ # [derive(Deserialize, Debug, Default, Clone)]
+ # [serde(default)]
+struct CpuStats {
+    cpu_usage: CpuUsage,
+    system_cpu_usage: Option<u64>,
+    online_cpus: Option<u32>,
+}
+
+ # [derive(Deserialize, Debug, Default, Clone)]
+ # [serde(default)]
+struct CpuUsage {
+    total_usage: u64,
+    percpu_usage: Option<Vec<u64>>,
+}
+
+ # [derive(Deserialize, Debug, Default)]
+ # [serde(default)]
+struct MemoryStats {
+    usage: u64,
+    limit: u64,
+    stats: HashMap<String, u64>,
+}
+
+ # [derive(Deserialize, Debug, Default)]
+ # [serde(default)]
+struct NetworkStats {
+    rx_bytes: u64,
+    tx_bytes: u64,
+}
+
+ # [derive(Deserialize, Debug, Default)]
+ # [serde(default)]
+struct BlkioStats {
+    io_service_bytes_recursive: Option<Vec<BlkioEntry>>,
+}
+
+ # [derive(Deserialize, Debug, Default)]
+ # [serde(default)]
+struct BlkioEntry {
+    op: String,
+    value: u64,
+}
+
+ # [derive(Deserialize, Debug, Default)]
+ # [serde(default)]
+struct PidsStats {
+    current: u64,
+}
+
+ # [derive(Deserialize, Debug)]
+ # [serde(rename_all = "PascalCase")]
+struct Listed {
+    id: String,
+}
+
+/// CPU usage since `previous`, as a percentage of one CPU
+fn cpu_percent(current: &CpuStats, previous: &CpuStats) -> f64 {
+    let cpu_delta = current.cpu_usage.total_usage as f64 - previous.cpu_usage.total_usage as f64;
+    let system_delta = current.system_cpu_usage.unwrap_or(0) as f64 - previous.system_cpu_usage.unwrap_or(0) as f64;
+    let cpus = current
+        .online_cpus
+        .map(|cpus| cpus as usize)
+        .or_else(|| current.cpu_usage.percpu_usage.as_ref().map(Vec::len))
+        .unwrap_or(1);
+    if cpu_delta > 0.0 && system_delta > 0.0 {
+        cpu_delta / system_delta * cpus as f64 * 100.0
+    }
+    else {
+        0.0
+    }
+}
+
+impl Sample {
+    /// Memory in use, not counting the page cache the kernel can reclaim
+    fn memory_used(&self) -> u64 {
+        // cgroup v1 reports total_inactive_file, v2 inactive_file
+        let cache = self
+            .memory_stats
+            .stats
+            .get("total_inactive_file")
+            .or_else(|| self.memory_stats.stats.get("inactive_file"))
+            .copied()
+            .unwrap_or(0);
+        self.memory_stats.usage.saturating_sub(cache)
+    }
+
+    fn network_io(&self) -> (u64, u64) {
+        self.networks
+            .iter()
+            .flat_map(|networks| networks.values())
+            .fold((0, 0), |(rx, tx), network| (rx + network.rx_bytes, tx + network.tx_bytes))
+    }
+
+    fn block_io(&self) -> (u64, u64) {
+        let mut read = 0;
+        let mut write = 0;
+        for entry in self.blkio_stats.io_service_bytes_recursive.iter().flatten() {
+            match entry.op.to_lowercase().as_str() {
+                "read" => read += entry.value,
+                "write" => write += entry.value,
+                _ => {}
+            }
+        }
+        (read, write)
+    }
+
+    /// The row shown for this sample, with CPU usage measured against `previous`
+    fn row(&self, container: &str, previous: &CpuStats) -> Value {
+        let used = self.memory_used();
+        let limit = self.memory_stats.limit;
+        let memory_percent = if limit > 0 {
+            used as f64 / limit as f64 * 100.0
+        }
+        else {
+            0.0
+        };
+        let (rx, tx) = self.network_io();
+        let (read, write) = self.block_io();
+        let decimal = |a: u64, b: u64| format!("{} / {}", units::human_size(a as f64), units::human_size(b as f64));
+        json!({
+            "Container": container,
+            "Name": self.name.trim_start_matches('/'),
+            "ID": self.id,
+            "CPUPerc": format!("{:.2}%", cpu_percent(&self.cpu_stats, previous)),
+            "MemUsage": format!("{} / {}", units::binary_size(used as f64), units::binary_size(limit as f64)),
+            "MemPerc": format!("{:.2}%", memory_percent),
+            "NetIO": decimal(rx, tx),
+            "BlockIO": decimal(read, write),
+            "PIDs": self.pids_stats.current.to_string(),
+        })
+    }
+}
+
+/// A row for a container without a sample yet
+fn placeholder(container: &str) -> Value {
+    json!({
+        "Container": container,
+        "Name": "--",
+        "ID": "--",
+        "CPUPerc": "--",
+        "MemUsage": "-- / --",
+        "MemPerc": "--",
+        "NetIO": "--",
+        "BlockIO": "--",
+        "PIDs": "--",
+    })
+}
+
+fn headers() -> Value {
+    json!({
+        "Container": "CONTAINER",
+        "Name": "NAME",
+        "ID": "CONTAINER ID",
+        "CPUPerc": "CPU %",
+        "MemUsage": "MEM USAGE / LIMIT",
+        "MemPerc": "MEM %",
+        "NetIO": "NET I/O",
+        "BlockIO": "BLOCK I/O",
+        "PIDs": "PIDS",
+    })
+}
+
+enum Output {
+    /// `table TEMPLATE`: tab separated cells, aligned under headers
+    Table(Template),
+    Lines(Template),
+    Json,
+}
+
+impl Output {
+    fn parse(format: Option<&str>) -> Result<Output, String> {
+        let format = match format {
+            None | Some("") | Some("table") => DEFAULT_FORMAT,
+            Some("json") => return Ok(Output::Json),
+            Some(format) => format,
+        };
+        // Shells pass `\t` through literally
+        let format = format.replace("\\t", "\t");
+        match format.strip_prefix("table") {
+            Some(table) => Ok(Output::Table(Template::parse(table.trim_start())?)),
+            None => Ok(Output::Lines(Template::parse(&format)?)),
+        }
+    }
+
+    fn print(&self, rows: &[Value]) -> Result<(), String> {
+        match self {
+            Output::Table(template) => {
+                let cells = |value: &Value| -> Result<Vec<String>, String> {
+                    Ok(template.render(value)?.split('\t').map(str::to_string).collect())
+                };
+                let headers = cells(&headers())?;
+                let rows = rows.iter().map(cells).collect::<Result<Vec<_>, _>>()?;
+                let headers: Vec<&str> = headers.iter().map(String::as_str).collect();
+                term::print_table(&headers, &rows);
+            }
+            Output::Lines(template) => {
+                for row in rows {
+                    println!("{}", template.render(row)?);
+                }
+            }
+            Output::Json => {
+                for row in rows {
+                    println!("{}", row);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What the stream of one container has produced so far
+struct Slot {
+    id: String,
+    row: Value,
+    error: Option<String>,
+    /// Whether the error has been printed
+    reported: bool,
+    done: bool,
+}
+
+type Slots = Arc<Mutex<Vec<Slot>>>;
+
+/// Adds a row for a container and keeps it updated from the daemon's stats stream
+fn follow(slots: &Slots, container: String, id: String) {
+    slots.lock().unwrap().push(Slot { id: id.clone(), row: placeholder(&container), error: None, reported: false, done: false });
+    let slots = Arc::clone(slots);
+    tokio::spawn(async move {
+        let engine = Engine::new();
+        let mut stream = engine.stream_values(Method::GET, format!("/containers/{}/stats?stream=1", id), None, None);
+        let mut previous: Option<CpuStats> = None;
+        let mut error = None;
+        while let Some(value) = stream.next().await {
+            let sample = value.and_then(|value| Ok(serde_json::from_value::<Sample>(value)?));
+            match sample {
+                Ok(sample) => {
+                    let row = sample.row(&container, previous.as_ref().unwrap_or(&sample.precpu_stats));
+                    previous = Some(sample.cpu_stats.clone());
+                    if let Some(slot) = slots.lock().unwrap().iter_mut().find(|slot| slot.id == id) {
+                        slot.row = row;
+                    }
+                }
+                Err(e) => {
+                    error = Some(engine::error_message(&e));
+                    break;
+                }
+            }
+        }
+        if let Some(slot) = slots.lock().unwrap().iter_mut().find(|slot| slot.id == id) {
+            // A failed container stays in the table, without figures
+            if error.is_some() {
+                slot.row = placeholder(&container);
+            }
+            slot.error = error;
+            slot.done = true;
+        }
+    });
+}
+
+/// Running containers, under their short IDs
+async fn running(engine: &Engine, all: bool) -> engine::Result<Vec<(String, String)>> {
+    let endpoint = engine::query("/containers/json", vec![("all", all.to_string())]);
+    let listed = engine.get_json::<Vec<Listed>>(&endpoint).await?;
+    Ok(listed.into_iter().map(|c| (term::short_id(&c.id), c.id)).collect())
+}
+
+pub async fn stats(containers: &[String], all: bool, no_stream: bool, format: Option<&str>) {
+    let output = match Output::parse(format) {
+        Ok(output) => output,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    };
+
+    // Without arguments every running container is shown, under its short ID
+    let engine = Engine::new();
+    let watch = containers.is_empty();
+    let targets: Vec<(String, String)> = if watch {
+        match running(&engine, all).await {
+            Ok(targets) => targets,
+            Err(e) => {
+                eprintln!("Error: {}", engine::error_message(&e));
+                process::exit(1);
+            }
+        }
+    }
+    else {
+        containers.iter().map(|c| (c.clone(), c.clone())).collect()
+    };
+
+    if no_stream {
+        let engine = &engine;
+        let samples = join_all(targets.iter().map(|(_, id)| async move {
+            engine.get_json::<Sample>(&format!("/containers/{}/stats?stream=0", id)).await
+        }))
+        .await;
+        let mut rows = Vec::new();
+        let mut failed = false;
+        for ((container, _), sample) in targets.iter().zip(samples) {
+            match sample {
+                // A one-shot sample carries the previous reading in precpu_stats
+                Ok(sample) => rows.push(sample.row(container, &sample.precpu_stats)),
+                Err(e) => {
+                    eprintln!("Error: {}", engine::error_message(&e));
+                    failed = true;
+                }
+            }
+        }
+        if let Err(e) = output.print(&rows) {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+        if failed {
+            process::exit(1);
+        }
+        return;
+    }
+
+    let slots: Slots = Arc::new(Mutex::new(Vec::new()));
+    for (container, id) in targets {
+        follow(&slots, container, id);
+    }
+
+    // Redrawing in place only makes sense on a terminal; otherwise each refresh is appended
+    let redraw = std::io::stdout().is_terminal();
+    loop {
+        tokio::time::sleep(REFRESH).await;
+        // Like docker, showing every container keeps watching for ones that start later
+        if watch {
+            match running(&engine, all).await {
+                Ok(targets) => {
+                    let known: Vec<String> = slots.lock().unwrap().iter().map(|slot| slot.id.clone()).collect();
+                    for (container, id) in targets {
+                        if !known.contains(&id) {
+                            follow(&slots, container, id);
+                        }
+                    }
+                }
+                Err(e) => eprintln!("Error: {}", engine::error_message(&e)),
+            }
+        }
+        let mut slots = slots.lock().unwrap();
+        let rows: Vec<Value> = slots.iter().map(|slot| slot.row.clone()).collect();
+        if redraw {
+            // Clear the screen and redraw from the top left corner
+            print!("\x1b[2J\x1b[H");
+        }
+        if let Err(e) = output.print(&rows) {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+        let _ = std::io::stdout().flush();
+        // Errors are reported as they happen; on a terminal they stay under the table
+        for slot in slots.iter_mut() {
+            if let Some(error) = &slot.error {
+                if redraw || !slot.reported {
+                    eprintln!("Error: {}", error);
+                    slot.reported = true;
+                }
+            }
+        }
+        if watch {
+            // Containers that stopped or went away leave the table
+            slots.retain(|slot| !slot.done);
+        }
+        else if slots.iter().all(|slot| slot.done) {
+            if slots.iter().any(|slot| slot.error.is_some()) {
+                process::exit(1);
+            }
+            return;
+        }
+    }
+}
@@ -0,0 +1,690 @@
+//! A subset of Go's text/template for `--format` options.
+//!
+//! Templates are evaluated against the JSON returned by the Engine API, so a
+//! format string behaves the same whichever object it is applied to. Supported
+//! are field chains (`.Config.Env`), `$`, pipelines, parentheses, string and
+//! number literals, `if`/`with`/`range` blocks with `else`, `{{-`/`-}}` trimming,
+//! and the functions docker users reach for most often.
+
+use crate::engine::{
+    self,
+    Engine
+};
+use serde_json::Value;
+use std::{
+    io::Write,
+    process
+};
+
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+enum Node {
+    Text(String),
+    Action(Pipeline),
+    If(Pipeline, Vec<Node>, Vec<Node>),
+    With(Pipeline, Vec<Node>, Vec<Node>),
+    Range(Pipeline, Vec<Node>, Vec<Node>),
+}
+
+type Pipeline = Vec<Command>;
+
+type Command = Vec<Operand>;
+
+enum Operand {
+    /// `.A.B`, evaluated against dot
+    Field(Vec<String>),
+    /// `$.A.B`, evaluated against the root object
+    Root(Vec<String>),
+    /// `(pipeline).A.B`
+    Group(Pipeline, Vec<String>),
+    Function(String),
+    Literal(Value),
+}
+
+enum Token {
+    Operand(Operand),
+    Pipe,
+    Open,
+    Close,
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Template, String> {
+        let mut items = split(source)?.into_iter();
+        let (nodes, end) = parse_nodes(&mut items)?;
+        match end {
+            None => Ok(Template { nodes }),
+            Some(keyword) => Err(format!("template: unexpected {{{{{}}}}}", keyword)),
+        }
+    }
+
+    pub fn render(&self, data: &Value) -> Result<String, String> {
+        let mut out = String::new();
+        render_nodes(&self.nodes, data, data, &mut out)?;
+        Ok(out)
+    }
+}
+
+/// Prints inspected objects as an indented JSON array, or once per object through `format`.
+///
+/// Returns false if the template could not be parsed or rendered.
+pub fn print_objects(objects: &[Value], format: Option<&str>) -> bool {
+    match format {
+        None | Some("") | Some("json") => {
+            let mut out = Vec::new();
+            let formatter = serde_json::ser::PrettyFormatter::with_indent(b"    ");
+            let mut serializer = serde_json::Serializer::with_formatter(&mut out, formatter);
+            serde::Serialize::serialize(objects, &mut serializer).unwrap();
+            let _ = std::io::stdout().write_all(&out);
+            println!();
+            true
+        }
+        Some(format) => {
+            let template = match Template::parse(format) {
+                Ok(template) => template,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    return false;
+                }
+            };
+            for object in objects {
+                match template.render(object) {
+                    Ok(text) => println!("{}", text),
+                    Err(e) => {
+                        eprintln!("Error: {}", e);
+                        return false;
+                    }
+                }
+            }
+            true
+        }
+    }
+}
+
+/// Fetches `{collection}/{name}/json` for every name and prints the objects found.
+///
+/// Every name is tried before exiting with status 1 if any of them failed.
+pub async fn inspect(collection: &str, names: &[String], format: Option<&str>) {
+    let engine = Engine::new();
+    let mut objects = Vec::new();
+    let mut failed = false;
+    for name in names {
+        match engine.get_json::<Value>(&format!("{}/{}/json", collection, name)).await {
+            Ok(object) => objects.push(object),
+            Err(e) => {
+                eprintln!("Error: {}", engine::error_message(&e));
+                failed = true;
+            }
+        }
+    }
+    if !print_objects(&objects, format) || failed {
+        process::exit(1);
+    }
+}
+
+enum Item {
+    Text(String),
+    Action(String),
+}
+
+/// Splits the source into text and the contents of `{{ }}` actions, applying trim markers
+fn split(source: &str) -> Result<Vec<Item>, String> {
+    let mut items = Vec::new();
+    let mut rest = source;
+    let mut trim_next = false;
+    while let Some(start) = rest.find("{{") {
+        let mut text = &rest[..start];
+        if trim_next {
+            text = text.trim_start();
+        }
+        let mut action = &rest[start + 2..];
+        if action.starts_with("- ") || action.starts_with("-\t") || action.starts_with("-\n") {
+            text = text.trim_end();
+            action = &action[1..];
+        }
+        if !text.is_empty() {
+            items.push(Item::Text(text.to_string()));
+        }
+        let end = find_action_end(action).ok_or("template: unclosed action")?;
+        let mut body = &action[..end];
+        trim_next = false;
+        if body.ends_with(" -") || body.ends_with("\t-") || body.ends_with("\n-") {
+            body = &body[..body.len() - 1];
+            trim_next = true;
+        }
+        let body = body.trim();
+        if !body.starts_with("/*") {
+            items.push(Item::Action(body.to_string()));
+        }
+        rest = &action[end + 2..];
+    }
+    let text = if trim_next { rest.trim_start() } else { rest };
+    if !text.is_empty() {
+        items.push(Item::Text(text.to_string()));
+    }
+    Ok(items)
+}
+
+/// Finds the closing `}}` of an action, skipping over string literals
+fn find_action_end(action: &str) -> Option<usize> {
+    let bytes = action.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'"' => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'"' {
+                    if bytes[i] == b'\\' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+            }
+            b'`' => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'`' {
+                    i += 1;
+                }
+            }
+            b'}' if bytes.get(i + 1) == Some(&b'}') => return Some(i),
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Parses nodes until `else`, `end` or the end of input, returning the keyword that stopped it
+fn parse_nodes(items: &mut std::vec::IntoIter<Item>) -> Result<(Vec<Node>, Option<String>), String> {
+    let mut nodes = Vec::new();
+    while let Some(item) = items.next() {
+        let action = match item {
+            Item::Text(text) => {
+                nodes.push(Node::Text(text));
+                continue;
+            }
+            Item::Action(action) => action,
+        };
+        let (keyword, rest) = match action.split_once(char::is_whitespace) {
+            Some((keyword, rest)) => (keyword, rest.trim()),
+            None => (action.as_str(), ""),
+        };
+        match keyword {
+            "end" | "else" => return Ok((nodes, Some(keyword.to_string()))),
+            "if" | "with" | "range" => {
+                let pipeline = parse_pipeline(rest)?;
+                let (body, end) = parse_nodes(items)?;
+                let otherwise = match end.as_deref() {
+                    Some("end") => Vec::new(),
+                    Some("else") => match parse_nodes(items)? {
+                        (otherwise, Some(end)) if end == "end" => otherwise,
+                        _ => return Err(format!("template: missing {{{{end}}}} for {}", keyword)),
+                    },
+                    _ => return Err(format!("template: missing {{{{end}}}} for {}", keyword)),
+                };
+                nodes.push(match keyword {
+                    "if" => Node::If(pipeline, body, otherwise),
+                    "with" => Node::With(pipeline, body, otherwise),
+                    _ => Node::Range(pipeline, body, otherwise),
+                });
+            }
+            _ => nodes.push(Node::Action(parse_pipeline(&action)?)),
+        }
+    }
+    Ok((nodes, None))
+}
+
+fn parse_pipeline(source: &str) -> Result<Pipeline, String> {
+    let tokens = tokenize(source)?;
+    let mut tokens = tokens.into_iter().peekable();
+    let pipeline = parse_tokens(&mut tokens)?;
+    if tokens.next().is_some() {
+        return Err(format!("template: unexpected \")\" in {}", source));
+    }
+    Ok(pipeline)
+}
+
+fn parse_tokens(tokens: &mut std::iter::Peekable<std::vec::IntoIter<Token>>) -> Result<Pipeline, String> {
+    let mut pipeline = Vec::new();
+    let mut command = Vec::new();
+    while let Some(token) = tokens.peek() {
+        match token {
+            Token::Close => break,
+            Token::Pipe => {
+                tokens.next();
+                if command.is_empty() {
+                    return Err("template: missing command before \"|\"".to_string());
+                }
+                pipeline.push(std::mem::take(&mut command));
+            }
+            Token::Open => {
+                tokens.next();
+                let group = parse_tokens(tokens)?;
+                match tokens.next() {
+                    Some(Token::Close) => {}
+                    _ => return Err("template: unclosed left paren".to_string()),
+                }
+                let fields = match tokens.peek() {
+                    Some(Token::Operand(Operand::Field(fields))) if !fields.is_empty() => {
+                        let fields = fields.clone();
+                        tokens.next();
+                        fields
+                    }
+                    _ => Vec::new(),
+                };
+                command.push(Operand::Group(group, fields));
+            }
+            Token::Operand(_) => {
+                if let Some(Token::Operand(operand)) = tokens.next() {
+                    command.push(operand);
+                }
+            }
+        }
+    }
+    if command.is_empty() {
+        return Err("template: missing value for command".to_string());
+    }
+    pipeline.push(command);
+    Ok(pipeline)
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        match c {
+            '|' => {
+                tokens.push(Token::Pipe);
+                i += 1;
+            }
+            '(' => {
+                tokens.push(Token::Open);
+                i += 1;
+            }
+            ')' => {
+                tokens.push(Token::Close);
+                i += 1;
+            }
+            '"' => {
+                let mut literal = String::new();
+                i += 1;
+                while i < chars.len() && chars[i] != '"' {
+                    if chars[i] == '\\' && i + 1 < chars.len() {
+                        i += 1;
+                        literal.push(match chars[i] {
+                            'n' => '\n',
+                            't' => '\t',
+                            other => other,
+                        });
+                    }
+                    else {
+                        literal.push(chars[i]);
+                    }
+                    i += 1;
+                }
+                if i == chars.len() {
+                    return Err("template: unterminated quoted string".to_string());
+                }
+                i += 1;
+                tokens.push(Token::Operand(Operand::Literal(Value::String(literal))));
+            }
+            '`' => {
+                let start = i + 1;
+                i = start;
+                while i < chars.len() && chars[i] != '`' {
+                    i += 1;
+                }
+                if i == chars.len() {
+                    return Err("template: unterminated raw quoted string".to_string());
+                }
+                tokens.push(Token::Operand(Operand::Literal(Value::String(chars[start..i].iter().collect()))));
+                i += 1;
+            }
+            _ => {
+                let start = i;
+                while i < chars.len() && !chars[i].is_whitespace() && !"|()".contains(chars[i]) {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                tokens.push(Token::Operand(parse_word(&word)?));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn parse_word(word: &str) -> Result<Operand, String> {
+    if word == "." {
+        return Ok(Operand::Field(Vec::new()));
+    }
+    if let Some(path) = word.strip_prefix('.') {
+        return Ok(Operand::Field(path.split('.').map(str::to_string).collect()));
+    }
+    if word == "$" {
+        return Ok(Operand::Root(Vec::new()));
+    }
+    if let Some(path) = word.strip_prefix("$.") {
+        return Ok(Operand::Root(path.split('.').map(str::to_string).collect()));
+    }
+    match word {
+        "true" => return Ok(Operand::Literal(Value::Bool(true))),
+        "false" => return Ok(Operand::Literal(Value::Bool(false))),
+        "nil" => return Ok(Operand::Literal(Value::Null)),
+        _ => {}
+    }
+    if let Ok(number) = serde_json::from_str::<serde_json::Number>(word) {
+        return Ok(Operand::Literal(Value::Number(number)));
+    }
+    if word.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        return Ok(Operand::Function(word.to_string()));
+    }
+    Err(format!("template: bad character in \"{}\"", word))
+}
+
+fn render_nodes(nodes: &[Node], dot: &Value, root: &Value, out: &mut String) -> Result<(), String> {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Action(pipeline) => match eval_pipeline(pipeline, dot, root)? {
+                Some(value) => out.push_str(&to_text(&value)),
+                None => out.push_str("<no value>"),
+            },
+            Node::If(pipeline, body, otherwise) => {
+                if truthy(&value_of(eval_pipeline(pipeline, dot, root)?)) {
+                    render_nodes(body, dot, root, out)?;
+                }
+                else {
+                    render_nodes(otherwise, dot, root, out)?;
+                }
+            }
+            Node::With(pipeline, body, otherwise) => {
+                let value = value_of(eval_pipeline(pipeline, dot, root)?);
+                if truthy(&value) {
+                    render_nodes(body, &value, root, out)?;
+                }
+                else {
+                    render_nodes(otherwise, dot, root, out)?;
+                }
+            }
+            Node::Range(pipeline, body, otherwise) => {
+                let items: Vec<Value> = match value_of(eval_pipeline(pipeline, dot, root)?) {
+                    Value::Array(items) => items,
+                    Value::Object(map) => map.into_iter().map(|(_, v)| v).collect(),
+                    Value::Null => Vec::new(),
+                    other => return Err(format!("template: range can't iterate over {}", to_text(&other))),
+                };
+                if items.is_empty() {
+                    render_nodes(otherwise, dot, root, out)?;
+                }
+                for item in &items {
+                    render_nodes(body, item, root, out)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A missing field is `None`: it prints as `<no value>` like a missing Go map key,
+/// and is nil everywhere else
+type Evaluated = Option<Value>;
+
+fn value_of(evaluated: Evaluated) -> Value {
+    evaluated.unwrap_or(Value::Null)
+}
+
+fn eval_pipeline(pipeline: &Pipeline, dot: &Value, root: &Value) -> Result<Evaluated, String> {
+    let mut piped: Option<Evaluated> = None;
+    for command in pipeline {
+        piped = Some(eval_command(command, dot, root, piped)?);
+    }
+    Ok(piped.unwrap_or(Some(Value::Null)))
+}
+
+fn eval_command(command: &Command, dot: &Value, root: &Value, piped: Option<Evaluated>) -> Result<Evaluated, String> {
+    if let Operand::Function(name) = &command[0] {
+        let mut args = command[1..]
+            .iter()
+            .map(|operand| eval_operand(operand, dot, root).map(value_of))
+            .collect::<Result<Vec<Value>, String>>()?;
+        if let Some(value) = piped {
+            args.push(value_of(value));
+        }
+        return call(name, args).map(Some);
+    }
+    if command.len() > 1 || piped.is_some() {
+        return Err("template: can't give argument to non-function".to_string());
+    }
+    eval_operand(&command[0], dot, root)
+}
+
+fn eval_operand(operand: &Operand, dot: &Value, root: &Value) -> Result<Evaluated, String> {
+    match operand {
+        Operand::Field(fields) => Ok(lookup(dot, fields)),
+        Operand::Root(fields) => Ok(lookup(root, fields)),
+        Operand::Group(pipeline, fields) => match eval_pipeline(pipeline, dot, root)? {
+            Some(value) => Ok(lookup(&value, fields)),
+            None => Ok(None),
+        },
+        Operand::Literal(value) => Ok(Some(value.clone())),
+        Operand::Function(name) => call(name, Vec::new()).map(Some),
+    }
+}
+
+/// Follows a field chain, returning `None` if any field is missing
+fn lookup(value: &Value, fields: &[String]) -> Evaluated {
+    let mut current = value;
+    for field in fields {
+        current = current.get(field)?;
+    }
+    Some(current.clone())
+}
+
+fn call(name: &str, args: Vec<Value>) -> Result<Value, String> {
+    let arity = |n: usize| {
+        if args.len() == n {
+            Ok(())
+        }
+        else {
+            Err(format!("template: wrong number of args for {}: want {} got {}", name, n, args.len()))
+        }
+    };
+    match name {
+        "json" => {
+            arity(1)?;
+            Ok(Value::String(serde_json::to_string(&args[0]).unwrap()))
+        }
+        "join" => {
+            arity(2)?;
+            let items = match &args[0] {
+                Value::Array(items) => items.iter().map(to_text).collect::<Vec<_>>(),
+                Value::Null => Vec::new(),
+                other => vec![to_text(other)],
+            };
+            Ok(Value::String(items.join(&to_text(&args[1]))))
+        }
+        "split" => {
+            arity(2)?;
+            let parts = to_text(&args[0])
+                .split(to_text(&args[1]).as_str())
+                .map(|s| Value::String(s.to_string()))
+                .collect();
+            Ok(Value::Array(parts))
+        }
+        "lower" => {
+            arity(1)?;
+            Ok(Value::String(to_text(&args[0]).to_lowercase()))
+        }
+        "upper" => {
+            arity(1)?;
+            Ok(Value::String(to_text(&args[0]).to_uppercase()))
+        }
+        "title" => {
+            arity(1)?;
+            let text = to_text(&args[0]);
+            let mut chars = text.chars();
+            Ok(Value::String(match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }))
+        }
+        "truncate" => {
+            arity(2)?;
+            let length = args[1].as_u64().ok_or("template: truncate length must be a number")? as usize;
+            Ok(Value::String(to_text(&args[0]).chars().take(length).collect()))
+        }
+        "len" => {
+            arity(1)?;
+            let length = match &args[0] {
+                Value::Array(items) => items.len(),
+                Value::Object(map) => map.len(),
+                Value::String(text) => text.len(),
+                Value::Null => 0,
+                other => return Err(format!("template: len of {}", to_text(other))),
+            };
+            Ok(Value::from(length))
+        }
+        "index" => {
+            if args.is_empty() {
+                return Err("template: wrong number of args for index".to_string());
+            }
+            let mut current = args[0].clone();
+            for key in &args[1..] {
+                current = match (&current, key) {
+                    (Value::Array(items), Value::Number(n)) => {
+                        let i = n.as_u64().ok_or("template: index must be a non-negative integer")? as usize;
+                        items.get(i).cloned().ok_or(format!("template: index out of range: {}", i))?
+                    }
+                    (Value::Object(map), Value::String(k)) => map.get(k).cloned().unwrap_or(Value::Null),
+                    (Value::Null, _) => Value::Null,
+                    _ => return Err(format!("template: can't index item of type {}", type_name(&current))),
+                };
+            }
+            Ok(current)
+        }
+        "print" => Ok(Value::String(args.iter().map(to_text).collect::<Vec<_>>().join(""))),
+        "println" => Ok(Value::String(args.iter().map(to_text).collect::<Vec<_>>().join(" ") + "\n")),
+        "eq" | "ne" => {
+            arity(2)?;
+            let equal = args[0] == args[1] || to_text(&args[0]) == to_text(&args[1]);
+            Ok(Value::Bool(equal == (name == "eq")))
+        }
+        "not" => {
+            arity(1)?;
+            Ok(Value::Bool(!truthy(&args[0])))
+        }
+        "and" => Ok(args.iter().find(|v| !truthy(v)).or(args.last()).cloned().unwrap_or(Value::Null)),
+        "or" => Ok(args.iter().find(|v| truthy(v)).or(args.last()).cloned().unwrap_or(Value::Null)),
+        _ => Err(format!("template: function \"{}\" not defined", name)),
+    }
+}
+
+fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64() != Some(0.0),
+        Value::String(s) => !s.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(map) => !map.is_empty(),
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "nil",
+        Value::Bool(_) => "bool",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "slice",
+        Value::Object(_) => "map",
+    }
+}
+
+/// Formats a value the way Go's `%v` prints the equivalent Go value
+fn to_text(value: &Value) -> String {
+    match value {
+        Value::Null => "<nil>".to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Number(n) => match n.as_f64() {
+            Some(f) if n.is_f64() && f.fract() == 0.0 && f.abs() < 1e15 => format!("{}", f as i64),
+            _ => n.to_string(),
+        },
+        Value::String(s) => s.clone(),
+        Value::Array(items) => format!("[{}]", items.iter().map(to_text).collect::<Vec<_>>().join(" ")),
+        Value::Object(map) => {
+            let mut entries: Vec<String> = map.iter().map(|(k, v)| format!("{}:{}", k, to_text(v))).collect();
+            entries.sort();
+            format!("map[{}]", entries.join(" "))
+        }
+    }
+}
+
+ # [cfg(test)]
+mod tests {
+    use super::Template;
+    use serde_json::{
+        json,
+        Value
+    };
+
+    fn render(source: &str, data: &Value) -> String {
+        Template::parse(source).unwrap().render(data).unwrap()
+    }
+
+    fn image() -> Value {
+        json!({
+            "Id": "sha256:5ec2e7",
+            "RepoDigests": ["alpine@sha256:beefcafe", "mirror/alpine@sha256:beefcafe"],
+            "Config": {"Env": ["PATH=/usr/bin", "LANG=C.UTF-8"], "Healthcheck": null},
+            "RootFS": {"Type": "layers", "Layers": ["sha256:aaa", "sha256:bbb"]},
+        })
+    }
+
+     # [test]
+    fn image_inspect_formats() {
+        assert_eq!(render("{{.Config.Env}}", &image()), "[PATH=/usr/bin LANG=C.UTF-8]");
+        assert_eq!(render("{{.RootFS.Layers}}", &image()), "[sha256:aaa sha256:bbb]");
+        assert_eq!(render("{{index .RepoDigests 0}}", &image()), "alpine@sha256:beefcafe");
+    }
+
+     # [test]
+    fn missing_fields_print_no_value() {
+        assert_eq!(render("{{.Missing}}", &image()), "<no value>");
+        assert_eq!(render("{{.Config.Missing.Deeper}}", &image()), "<no value>");
+        assert_eq!(render("{{(.Missing).Deeper}}", &image()), "<no value>");
+    }
+
+     # [test]
+    fn missing_fields_are_falsy() {
+        let container = json!({"State": {"Status": "running"}});
+        assert_eq!(render("{{if .State.Health}}HEALTHY{{else}}none{{end}}", &container), "none");
+        assert_eq!(render("{{with .Nope}}yes{{end}}", &container), "");
+        assert_eq!(render("{{if not .Nope}}absent{{end}}", &container), "absent");
+        assert_eq!(render("{{or .Nope \"fallback\"}}", &container), "fallback");
+        assert_eq!(render("{{if and .State .Nope}}both{{else}}not both{{end}}", &container), "not both");
+        assert_eq!(render("{{range .Nope}}item{{else}}empty{{end}}", &container), "empty");
+    }
+
+     # [test]
+    fn missing_fields_are_nil_in_functions() {
+        assert_eq!(render("{{json .Missing}}", &image()), "null");
+        assert_eq!(render("{{.Missing | json}}", &image()), "null");
+        assert_eq!(render("{{len .Missing}}", &image()), "0");
+    }
+
+     # [test]
+    fn present_fields_are_unchanged() {
+        assert_eq!(render("{{json .Config.Healthcheck}}", &image()), "null");
+        assert_eq!(render("{{if .RootFS}}{{.RootFS.Type}}{{end}}", &image()), "layers");
+        assert_eq!(render("{{with .Config}}{{len .Env}}{{end}}", &image()), "2");
+    }
+}
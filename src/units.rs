@@ -0,0 +1,126 @@
+//! Human readable sizes and durations, following the conventions of the docker CLI.
+
+use std::time::Duration;
+
+const BINARY_UNITS: [char; 6] = ['k', 'm', 'g', 't', 'p', 'e'];
+
+const BINARY_ABBRS: [&str; 9] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB", "ZiB", "YiB"];
+
+const DECIMAL_UNITS: [&str; 9] = ["B", "kB", "MB", "GB", "TB", "PB", "EB", "ZB", "YB"];
+
+/// Formats a size with three significant digits and decimal units, e.g. `72.8MB`
+pub fn human_size(size: f64) -> String {
+    let mut size = size;
+    let mut unit = 0;
+    while size >= 1000.0 && unit < DECIMAL_UNITS.len() - 1 {
+        size /= 1000.0;
+        unit += 1;
+    }
+    let precision = if size >= 100.0 {
+        0
+    }
+    else if size >= 10.0 {
+        1
+    }
+    else {
+        2
+    };
+    let mut number = format!("{:.*}", precision, size);
+    if number.contains('.') {
+        number = number.trim_end_matches('0').trim_end_matches('.').to_string();
+    }
+    format!("{}{}", number, DECIMAL_UNITS[unit])
+}
+
+/// Formats a size with four significant digits and binary units, e.g. `1.953GiB`
+pub fn binary_size(size: f64) -> String {
+    let mut size = size;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < BINARY_ABBRS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    let integer_digits = if size >= 1.0 {
+        size.log10() as usize + 1
+    }
+    else {
+        1
+    };
+    let mut number = format!("{:.*}", 4usize.saturating_sub(integer_digits), size);
+    if number.contains('.') {
+        number = number.trim_end_matches('0').trim_end_matches('.').to_string();
+    }
+    format!("{}{}", number, BINARY_ABBRS[unit])
+}
+
+/// Describes a duration the way `docker ps` does, e.g. `About an hour` or `3 weeks`
+pub fn human_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    let minutes = seconds / 60;
+    let hours = (duration.as_secs_f64() / 3600.0 + 0.5) as u64;
+    if seconds < 1 {
+        "Less than a second".to_string()
+    }
+    else if seconds == 1 {
+        "1 second".to_string()
+    }
+    else if seconds < 60 {
+        format!("{} seconds", seconds)
+    }
+    else if minutes == 1 {
+        "About a minute".to_string()
+    }
+    else if minutes < 60 {
+        format!("{} minutes", minutes)
+    }
+    else if hours == 1 {
+        "About an hour".to_string()
+    }
+    else if hours < 48 {
+        format!("{} hours", hours)
+    }
+    else if hours < 24 * 7 * 2 {
+        format!("{} days", hours / 24)
+    }
+    else if hours < 24 * 30 * 2 {
+        format!("{} weeks", hours / 24 / 7)
+    }
+    else if hours < 24 * 365 * 2 {
+        format!("{} months", hours / 24 / 30)
+    }
+    else {
+        format!("{} years", hours / 24 / 365)
+    }
+}
+
+/// How long ago a unix timestamp was, e.g. `2 weeks ago`
+pub fn time_ago(timestamp: i64) -> String {
+    let elapsed = chrono::Utc::now().timestamp() - timestamp;
+    format!("{} ago", human_duration(Duration::from_secs(elapsed.max(0) as u64)))
+}
+
+/// Parses a size such as `512m`, `1.5g` or `1024` into bytes, using binary units
+pub fn parse_bytes(size: &str) -> Result<i64, String> {
+    let invalid = || format!("invalid size: '{}'", size);
+    let lower = size.trim().to_lowercase();
+    let mut digits = lower.as_str();
+    for suffix in ["ib", "b", "i"] {
+        if let Some(stripped) = digits.strip_suffix(suffix) {
+            digits = stripped;
+            break;
+        }
+    }
+    let digits = digits.trim_end();
+    let (number, multiplier) = match digits.chars().last() {
+        Some(unit) if unit.is_ascii_alphabetic() => {
+            let power = BINARY_UNITS.iter().position(|u| *u == unit).ok_or_else(invalid)? + 1;
+            (digits[..digits.len() - 1].trim_end(), 1024f64.powi(power as i32))
+        }
+        _ => (digits, 1.0),
+    };
+    let number: f64 = number.parse().map_err(|_| invalid())?;
+    if number < 0.0 || !number.is_finite() {
+        return Err(invalid());
+    }
+    Ok((number * multiplier) as i64)
+}